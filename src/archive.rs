@@ -0,0 +1,62 @@
+use std::io::{Read, Seek};
+
+use failure::Fallible;
+use zip::ZipArchive;
+
+/// The kinds of archive which can be read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Archive {
+    Tar,
+    Zip,
+}
+
+impl Archive {
+    /// Detects an archive from its magic bytes. Compressed tar files are detected after
+    /// decompression.
+    pub fn detect(buf: &[u8]) -> Option<Self> {
+        if buf.starts_with(b"PK\x03\x04") || buf.starts_with(b"PK\x05\x06") {
+            Some(Archive::Zip)
+        } else if buf.get(257..262) == Some(b"ustar") {
+            Some(Archive::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Calls `f` with the path and contents of each file in the archive. Any leading `./`
+    /// is removed from the paths.
+    pub fn entries<R, F>(self, rdr: R, mut f: F) -> Fallible<()>
+    where
+        R: Read + Seek,
+        F: FnMut(&str, &mut dyn Read) -> Fallible<()>,
+    {
+        match self {
+            Archive::Tar => {
+                let mut tar = tar::Archive::new(rdr);
+                for entry in tar.entries()? {
+                    let mut entry = entry?;
+                    if entry.header().entry_type().is_file() {
+                        let name = entry.path()?.to_string_lossy().into_owned();
+                        f(normalize(&name), &mut entry)?;
+                    }
+                }
+            }
+            Archive::Zip => {
+                let mut zip = ZipArchive::new(rdr)?;
+                for index in 0..zip.len() {
+                    let mut file = zip.by_index(index)?;
+                    if file.is_file() {
+                        let name = file.name().to_owned();
+                        f(normalize(&name), &mut file)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes any leading `./` from the path of an entry, as written by `tar -C dir .`.
+pub fn normalize(name: &str) -> &str {
+    name.trim_start_matches("./")
+}
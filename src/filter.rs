@@ -0,0 +1,134 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use failure::{bail, format_err, Error, ResultExt};
+use regex::Regex;
+
+/// A predicate on array elements, of the form `<pointer> <op> <value>`.
+#[derive(Debug)]
+pub struct Filter {
+    pointer: String,
+    op: Op,
+}
+
+#[derive(Debug)]
+enum Op {
+    Eq(json::Value),
+    Ne(json::Value),
+    Lt(json::Value),
+    Gt(json::Value),
+    Match(Regex),
+    Exists,
+}
+
+impl Filter {
+    pub fn matches(&self, value: &json::Value) -> bool {
+        let value = match value.pointer(&self.pointer) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        match &self.op {
+            Op::Eq(expected) => equal(value, expected),
+            Op::Ne(expected) => !equal(value, expected),
+            Op::Lt(expected) => compare(value, expected) == Some(Ordering::Less),
+            Op::Gt(expected) => compare(value, expected) == Some(Ordering::Greater),
+            Op::Match(regex) => match value {
+                json::Value::String(string) => regex.is_match(string),
+                _ => false,
+            },
+            Op::Exists => true,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (pointer, rest) = match split_token(s) {
+            (token, _) if is_op(token) => ("", s),
+            (token, rest) => (token, rest),
+        };
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            bail!("Invalid JSON pointer `{}`", pointer);
+        }
+
+        let (op, value) = split_token(rest);
+        let op = match op {
+            "==" => Op::Eq(parse_value(value)),
+            "!=" => Op::Ne(parse_value(value)),
+            "<" => Op::Lt(parse_value(value)),
+            ">" => Op::Gt(parse_value(value)),
+            "=~" => {
+                let pattern = match parse_value(value) {
+                    json::Value::String(pattern) => pattern,
+                    _ => value.to_owned(),
+                };
+                Op::Match(Regex::new(&pattern).context(format!("Invalid regex `{}`", pattern))?)
+            }
+            "exists" if value.is_empty() => Op::Exists,
+            "exists" => bail!("Unexpected value `{}` after `exists`", value),
+            "" => bail!("Missing operator in filter `{}`", s),
+            op => return Err(format_err!("Unknown operator `{}`", op)),
+        };
+
+        Ok(Filter {
+            pointer: pointer.to_owned(),
+            op,
+        })
+    }
+}
+
+fn is_op(token: &str) -> bool {
+    matches!(token, "==" | "!=" | "<" | ">" | "=~" | "exists")
+}
+
+fn split_token(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(idx) => (&s[..idx], s[idx..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Values which are not valid JSON are treated as bare strings.
+fn parse_value(s: &str) -> json::Value {
+    json::from_str(s).unwrap_or_else(|_| json::Value::String(s.to_owned()))
+}
+
+fn equal(lhs: &json::Value, rhs: &json::Value) -> bool {
+    match (lhs, rhs) {
+        (json::Value::Number(_), json::Value::Number(_)) => {
+            compare(lhs, rhs) == Some(Ordering::Equal)
+        }
+        (json::Value::Array(lhs), json::Value::Array(rhs)) => {
+            lhs.len() == rhs.len() && lhs.iter().zip(rhs).all(|(lhs, rhs)| equal(lhs, rhs))
+        }
+        (json::Value::Object(lhs), json::Value::Object(rhs)) => {
+            lhs.len() == rhs.len()
+                && lhs
+                    .iter()
+                    .all(|(key, lhs)| rhs.get(key).is_some_and(|rhs| equal(lhs, rhs)))
+        }
+        _ => lhs == rhs,
+    }
+}
+
+fn compare(lhs: &json::Value, rhs: &json::Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (json::Value::Number(lhs), json::Value::Number(rhs)) => {
+            match (integer(lhs), integer(rhs)) {
+                (Some(lhs), Some(rhs)) => Some(lhs.cmp(&rhs)),
+                _ => lhs.as_f64()?.partial_cmp(&rhs.as_f64()?),
+            }
+        }
+        (json::Value::String(lhs), json::Value::String(rhs)) => Some(lhs.cmp(rhs)),
+        _ => None,
+    }
+}
+
+/// The value of an integer literal, which may be too large to compare exactly as a float.
+fn integer(number: &json::Number) -> Option<i128> {
+    number.to_string().parse().ok()
+}
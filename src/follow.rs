@@ -0,0 +1,154 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use failure::{Fail, Fallible, ResultExt};
+
+use crate::io::ReadWrapper;
+
+/// A device and inode number.
+type FileId = (u64, u64);
+
+/// Reads lines appended to a file, like `tail -f`. If the file is truncated it is read
+/// again from the start, and if it is replaced, such as by log rotation, the new file is
+/// opened.
+pub struct Follower {
+    path: PathBuf,
+    label: String,
+    rdr: ReadWrapper<File>,
+    id: Option<FileId>,
+    offset: u64,
+    // The start of a line which has not been completely written yet.
+    partial: Vec<u8>,
+}
+
+impl Follower {
+    /// Opens a file to follow from its current end.
+    pub fn new(path: impl Into<PathBuf>) -> Fallible<Self> {
+        let path = path.into();
+        let label = format!("file `{}`", path.display());
+        let (mut rdr, id) = open(&path, &label)?;
+        let offset = rdr.seek(SeekFrom::End(0))?;
+        Ok(Follower {
+            path,
+            label,
+            rdr,
+            id,
+            offset,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Calls `f` with each complete, non-blank line written since the last call.
+    pub fn poll<F>(&mut self, mut f: F) -> Fallible<()>
+    where
+        F: FnMut(&[u8]) -> Fallible<()>,
+    {
+        self.read(&mut f)?;
+        match fs::metadata(&self.path) {
+            Ok(meta) if file_id(&meta) != self.id => {
+                log::info!("The {} was replaced, reopening it.", self.label);
+                let (rdr, id) = open(&self.path, &self.label)?;
+                self.rdr = rdr;
+                self.id = id;
+                self.restart(&mut f)
+            }
+            Ok(meta) if meta.len() < self.offset => {
+                log::info!(
+                    "The {} was truncated, reading it from the start.",
+                    self.label
+                );
+                self.rdr.seek(SeekFrom::Start(0))?;
+                self.restart(&mut f)
+            }
+            Ok(_) => Ok(()),
+            // The file may be missing for a moment while it is rotated.
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err
+                .context(format!("Failed to read metadata of {}", self.label))
+                .into()),
+        }
+    }
+
+    fn restart<F>(&mut self, f: &mut F) -> Fallible<()>
+    where
+        F: FnMut(&[u8]) -> Fallible<()>,
+    {
+        if !self.partial.is_empty() {
+            log::warn!("Discarding an incomplete line from the {}.", self.label);
+            self.partial.clear();
+        }
+        self.offset = 0;
+        self.read(f)
+    }
+
+    fn read<F>(&mut self, f: &mut F) -> Fallible<()>
+    where
+        F: FnMut(&[u8]) -> Fallible<()>,
+    {
+        self.offset += self.rdr.read_to_end(&mut self.partial)? as u64;
+        if let Some(end) = self.partial.iter().rposition(|&byte| byte == b'\n') {
+            let rest = self.partial.split_off(end + 1);
+            for line in self.partial.split(|&byte| byte == b'\n') {
+                if !line.iter().all(u8::is_ascii_whitespace) {
+                    f(line)?;
+                }
+            }
+            self.partial = rest;
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether a file has been modified, from its modification time and size.
+pub struct Watcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+impl Watcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Watcher {
+            path: path.into(),
+            stamp: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file exists and has changed since the last call, or this is the first
+    /// call.
+    pub fn changed(&mut self) -> bool {
+        let stamp = fs::metadata(&self.path)
+            .and_then(|meta| Ok((meta.modified()?, meta.len())))
+            .ok();
+        if stamp.is_some() && stamp != self.stamp {
+            self.stamp = stamp;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn open(path: &Path, label: &str) -> Fallible<(ReadWrapper<File>, Option<FileId>)> {
+    let file = File::open(path).context(format!("Failed to open {}", label))?;
+    let id = file_id(&file.metadata()?);
+    Ok((ReadWrapper::new(file, label.to_owned()), id))
+}
+
+/// Identifies a file independently of its path, where the platform allows it.
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> Option<FileId> {
+    None
+}
@@ -0,0 +1,145 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use failure::{bail, Error};
+use structopt::StructOpt;
+
+use crate::ser::{Encoding, Foreign};
+
+/// The syntax of the input.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Jsonc,
+    Json5,
+    Yaml,
+    Toml,
+    Cbor,
+    MsgPack,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Opts {
+    /// The format of the input. If not set, this is detected from the file extension, or
+    /// from the first bytes of binary input.
+    #[structopt(
+        long,
+        raw(possible_values = "Format::NAMES", case_insensitive = "true")
+    )]
+    from: Option<Format>,
+    /// Parse the input as JSON with comments and trailing commas. This is detected
+    /// automatically for `.jsonc` files and editor configuration such as `tsconfig.json`.
+    #[structopt(long, conflicts_with = "json5", conflicts_with = "from")]
+    jsonc: bool,
+    /// Parse the input as JSON5. This is detected automatically for `.json5` files.
+    #[structopt(long, conflicts_with = "from")]
+    json5: bool,
+    /// The encoding used to display byte strings in CBOR and MessagePack input.
+    #[structopt(
+        long,
+        default_value = "base64",
+        raw(possible_values = "Encoding::NAMES", case_insensitive = "true")
+    )]
+    bytes: Encoding,
+}
+
+impl Opts {
+    /// The format of the input, if it was given or can be detected from the file name.
+    pub fn format(&self, path: Option<&Path>) -> Option<Format> {
+        if self.json5 {
+            Some(Format::Json5)
+        } else if self.jsonc {
+            Some(Format::Jsonc)
+        } else if let Some(format) = self.from {
+            Some(format)
+        } else {
+            path.and_then(Format::detect)
+        }
+    }
+
+    pub fn bytes(&self) -> Encoding {
+        self.bytes
+    }
+}
+
+impl Format {
+    const NAMES: &'static [&'static str] =
+        &["json", "jsonc", "json5", "yaml", "toml", "cbor", "msgpack"];
+
+    /// Guesses the format of binary input from its first bytes. Only the CBOR
+    /// self-describing tag and the MessagePack map markers with 16 and 32-bit lengths are
+    /// recognized, since the first bytes of small CBOR and MessagePack values overlap, and
+    /// other input needs the --from option.
+    pub fn sniff(buf: &[u8]) -> Option<Self> {
+        match buf {
+            [0xd9, 0xd9, 0xf7, ..] => Some(Format::Cbor),
+            [0xde..=0xdf, ..] => Some(Format::MsgPack),
+            _ => None,
+        }
+    }
+
+    /// The format to transcode the input from, if it is not a kind of JSON.
+    pub fn foreign(self, bytes: Encoding) -> Option<Foreign> {
+        match self {
+            Format::Json | Format::Jsonc | Format::Json5 => None,
+            Format::Yaml => Some(Foreign::Yaml),
+            Format::Toml => Some(Foreign::Toml),
+            Format::Cbor => Some(Foreign::Cbor(bytes)),
+            Format::MsgPack => Some(Foreign::MsgPack(bytes)),
+        }
+    }
+
+    fn detect(path: &Path) -> Option<Self> {
+        let mut name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        for ext in &[".gz", ".zst", ".xz"] {
+            if name.ends_with(ext) {
+                name.truncate(name.len() - ext.len());
+                break;
+            }
+        }
+        let in_vscode = path
+            .parent()
+            .and_then(Path::file_name)
+            .is_some_and(|dir| dir == ".vscode");
+
+        if name.ends_with(".json5") {
+            Some(Format::Json5)
+        } else if name.ends_with(".jsonc")
+            || (name.ends_with(".json")
+                && (in_vscode
+                    || name.starts_with("tsconfig")
+                    || name.starts_with("jsconfig")
+                    || name == "devcontainer.json"
+                    || name == ".devcontainer.json"))
+        {
+            Some(Format::Jsonc)
+        } else if name.ends_with(".yaml") || name.ends_with(".yml") {
+            Some(Format::Yaml)
+        } else if name.ends_with(".toml") {
+            Some(Format::Toml)
+        } else if name.ends_with(".cbor") {
+            Some(Format::Cbor)
+        } else if name.ends_with(".msgpack") || name.ends_with(".mpk") {
+            Some(Format::MsgPack)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "jsonc" => Ok(Format::Jsonc),
+            "json5" => Ok(Format::Json5),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            "cbor" => Ok(Format::Cbor),
+            "msgpack" | "messagepack" => Ok(Format::MsgPack),
+            _ => bail!("Unknown format `{}`", s),
+        }
+    }
+}
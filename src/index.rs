@@ -0,0 +1,552 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fs::{self, File, Metadata};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Read};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use failure::{bail, ensure, Fallible, ResultExt};
+use memmap2::Mmap;
+
+/// Which records of newline-delimited JSON to read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Select {
+    /// The last `n` records.
+    Tail(usize),
+    /// The record numbered `k`, counting from 1.
+    Record(usize),
+}
+
+/// The number of records between the offsets kept in an index.
+const STRIDE: usize = 1024;
+const MAGIC: &[u8; 8] = b"jvindex1";
+
+/// The offsets of every `STRIDE`th record in a file, so a record can be found without
+/// reading the whole file. The size and modification time of the file are kept, so an
+/// index for an older version of the file is not used.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LineIndex {
+    len: u64,
+    mtime: (u64, u32),
+    count: usize,
+    offsets: Vec<u64>,
+}
+
+impl LineIndex {
+    /// Indexes the records in the contents of a file.
+    pub fn build(data: &[u8], len: u64, mtime: (u64, u32)) -> Self {
+        let mut count = 0;
+        let mut offsets = Vec::new();
+        for (offset, _) in records(data) {
+            if count % STRIDE == 0 {
+                offsets.push(offset as u64);
+            }
+            count += 1;
+        }
+        LineIndex {
+            len,
+            mtime,
+            count,
+            offsets,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        for word in [
+            self.len,
+            self.mtime.0,
+            u64::from(self.mtime.1),
+            self.count as u64,
+        ] {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        for offset in &self.offsets {
+            buf.extend_from_slice(&offset.to_le_bytes());
+        }
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let mut words = buf.strip_prefix(MAGIC)?.chunks(8).map(|word| {
+            let word: [u8; 8] = word.try_into().ok()?;
+            Some(u64::from_le_bytes(word))
+        });
+        let mut next = || words.next().flatten();
+        let (len, secs, nanos, count) = (next()?, next()?, next()?, next()?);
+        let offsets: Option<Vec<_>> = words.collect();
+        let index = LineIndex {
+            len,
+            mtime: (secs, nanos.try_into().ok()?),
+            count: count.try_into().ok()?,
+            offsets: offsets?,
+        };
+        if index.offsets.len() == index.count.div_ceil(STRIDE) {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+/// Loads the index of a file from the data directory, if one was saved for the version of
+/// the file with metadata `meta`.
+pub fn load(dir: &Path, path: &Path, meta: &Metadata) -> Option<LineIndex> {
+    let stamp = stamp(meta).ok()?;
+    let index = LineIndex::from_bytes(&fs::read(index_path(dir, path, "lines").ok()?).ok()?)?;
+    if (index.len, index.mtime) == stamp {
+        Some(index)
+    } else {
+        log::debug!("Ignoring outdated index of `{}`.", path.display());
+        None
+    }
+}
+
+/// Loads the index of a file, or builds it from the file's contents and saves it in the
+/// data directory. `meta` is the metadata of the file `data` was read from.
+pub fn open(dir: &Path, path: &Path, data: &[u8], meta: &Metadata) -> Fallible<LineIndex> {
+    if let Some(index) = load(dir, path, meta) {
+        return Ok(index);
+    }
+
+    let (len, mtime) = stamp(meta)?;
+    let index = LineIndex::build(data, len, mtime);
+    log::debug!("Indexed {} records in `{}`.", index.count, path.display());
+    let saved = fs::create_dir_all(dir)
+        .and_then(|()| fs::write(index_path(dir, path, "lines")?, index.to_bytes()));
+    if let Err(err) = saved {
+        log::warn!("Failed to save index of `{}`: {}", path.display(), err);
+    }
+    Ok(index)
+}
+
+/// The size and modification time of a file.
+fn stamp(meta: &Metadata) -> Fallible<(u64, (u64, u32))> {
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?;
+    Ok((meta.len(), (mtime.as_secs(), mtime.subsec_nanos())))
+}
+
+/// Indexes are named after the file's name, a hash of its full path and the kind of index,
+/// and kept in the data directory so `clean` removes them along with the data files.
+fn index_path(dir: &Path, path: &Path, kind: &str) -> io::Result<PathBuf> {
+    let path = path.canonicalize()?;
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(dir.join(format!("{}.{:016x}.{}", name, hasher.finish(), kind)))
+}
+
+const TREE_MAGIC: &[u8; 8] = b"jvtree01";
+// The number of words in the header and in each entry of a tree index.
+const TREE_HEADER: usize = 5;
+const TREE_ENTRY: usize = 5;
+
+/// The byte ranges of the objects and arrays in a JSON document, sorted by their pointers
+/// so a value can be found without reading the document. The index is mapped into
+/// memory, and begins with the size and modification time of the document, followed by
+/// a table with the offset and length of each pointer, the range of its value and
+/// whether it is an array, and then the pointers themselves.
+pub struct TreeIndex {
+    map: Mmap,
+    count: usize,
+}
+
+/// An object or array in a document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Container {
+    pub range: Range<usize>,
+    pub array: bool,
+}
+
+impl Container {
+    /// The contents of the container in `data`, if they still begin and end with its
+    /// brackets. This catches most changes to a file which kept its size and
+    /// modification time.
+    pub fn slice<'a>(&self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let bytes = data.get(self.range.clone())?;
+        let (open, close) = if self.array {
+            (b'[', b']')
+        } else {
+            (b'{', b'}')
+        };
+        if bytes.first() == Some(&open) && bytes.last() == Some(&close) {
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+}
+
+impl TreeIndex {
+    /// Finds the object or array at a JSON pointer.
+    pub fn get(&self, ptr: &str) -> Option<Container> {
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let entry = |field| self.word(TREE_HEADER + mid * TREE_ENTRY + field);
+            let (offset, len) = (entry(0)? as usize, entry(1)? as usize);
+            let key = self.map.get(offset..offset.checked_add(len)?)?;
+            match key.cmp(ptr.as_bytes()) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => {
+                    return Some(Container {
+                        range: entry(2)? as usize..entry(3)? as usize,
+                        array: entry(4)? != 0,
+                    })
+                }
+            }
+        }
+        None
+    }
+
+    fn word(&self, index: usize) -> Option<u64> {
+        let word = self.map.get(index * 8..index * 8 + 8)?;
+        Some(u64::from_le_bytes(word.try_into().ok()?))
+    }
+}
+
+/// Indexes the objects and arrays in a file, saving the index in the data directory. Files
+/// containing several documents are not indexed.
+pub fn build_tree(dir: &Path, path: &Path) -> Fallible<()> {
+    let mut data = Vec::new();
+    let mut file = File::open(path).context(format!("Failed to open `{}`", path.display()))?;
+    let meta = file.metadata()?;
+    file.read_to_end(&mut data)
+        .context(format!("Failed to read `{}`", path.display()))?;
+    let mut scanner = Scanner {
+        data: &data,
+        pos: 0,
+        entries: Vec::new(),
+    };
+    let scanned = scanner.value(&mut String::new());
+    scanner.skip_whitespace();
+    if scanned.is_none() || scanner.pos != data.len() {
+        log::debug!(
+            "Not indexing `{}`, which is not a single document.",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let entries = dedup(scanner.entries);
+    let (len, (secs, nanos)) = stamp(&meta)?;
+    let magic = u64::from_le_bytes(*TREE_MAGIC);
+    let mut words = vec![magic, len, secs, u64::from(nanos), entries.len() as u64];
+    let mut offset = ((TREE_HEADER + entries.len() * TREE_ENTRY) * 8) as u64;
+    for (ptr, container) in &entries {
+        words.extend_from_slice(&[
+            offset,
+            ptr.len() as u64,
+            container.range.start as u64,
+            container.range.end as u64,
+            u64::from(container.array),
+        ]);
+        offset += ptr.len() as u64;
+    }
+    let mut buf: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    for (ptr, _) in &entries {
+        buf.extend_from_slice(ptr.as_bytes());
+    }
+
+    let index = index_path(dir, path, "tree")?;
+    fs::write(&index, buf).context(format!("Failed to write `{}`", index.display()))?;
+    log::debug!(
+        "Indexed {} objects and arrays in `{}`.",
+        entries.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Sorts the entries by pointer. A document with duplicate keys holds the last value of
+/// each, so only the last entry for a pointer is kept, and entries inside the values it
+/// replaced are dropped.
+fn dedup(mut entries: Vec<(String, Container)>) -> Vec<(String, Container)> {
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries.dedup_by(|next, prev| {
+        let duplicate = next.0 == prev.0;
+        if duplicate {
+            mem::swap(next, prev);
+        }
+        duplicate
+    });
+    let mut kept: Vec<(String, Container)> = Vec::with_capacity(entries.len());
+    for (ptr, container) in entries {
+        let inside = match ptr.rfind('/') {
+            Some(end) => kept
+                .binary_search_by(|(parent, _)| parent.as_str().cmp(&ptr[..end]))
+                .is_ok_and(|i| {
+                    let parent = &kept[i].1.range;
+                    parent.start <= container.range.start && container.range.end <= parent.end
+                }),
+            None => true,
+        };
+        if inside {
+            kept.push((ptr, container));
+        }
+    }
+    kept
+}
+
+/// Loads the tree index of a file from the data directory, if one was saved for the
+/// version of the file with metadata `meta`.
+pub fn load_tree(dir: &Path, path: &Path, meta: &Metadata) -> Option<TreeIndex> {
+    let file = File::open(index_path(dir, path, "tree").ok()?).ok()?;
+    // Safety: the index is only replaced by `build_tree`, which writes a new file.
+    let map = unsafe { Mmap::map(&file) }.ok()?;
+    if !map.starts_with(TREE_MAGIC) {
+        return None;
+    }
+    let mut index = TreeIndex { map, count: 0 };
+    let (len, secs, nanos) = (index.word(1)?, index.word(2)?, index.word(3)?);
+    index.count = index.word(4)?.try_into().ok()?;
+    if (len, (secs, nanos.try_into().ok()?)) == stamp(meta).ok()? {
+        Some(index)
+    } else {
+        log::debug!("Ignoring outdated tree index of `{}`.", path.display());
+        None
+    }
+}
+
+/// Finds the byte ranges of the objects and arrays in a JSON document.
+struct Scanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+    entries: Vec<(String, Container)>,
+}
+
+impl Scanner<'_> {
+    fn value(&mut self, ptr: &mut String) -> Option<()> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let array = match *self.data.get(self.pos)? {
+            b'{' => false,
+            b'[' => true,
+            b'"' => return self.string(),
+            _ => {
+                while self
+                    .data
+                    .get(self.pos)
+                    .is_some_and(|byte| !b",]} \t\r\n".contains(byte))
+                {
+                    self.pos += 1;
+                }
+                return if self.pos > start { Some(()) } else { None };
+            }
+        };
+        self.pos += 1;
+        let slot = self.entries.len();
+        self.entries.push((
+            ptr.clone(),
+            Container {
+                range: start..start,
+                array,
+            },
+        ));
+
+        let close = if array { b']' } else { b'}' };
+        self.skip_whitespace();
+        if self.data.get(self.pos) == Some(&close) {
+            self.pos += 1;
+        } else {
+            for index in 0.. {
+                let len = ptr.len();
+                if array {
+                    ptr.push_str(&format!("/{}", index));
+                } else {
+                    self.skip_whitespace();
+                    let key_start = self.pos;
+                    self.string()?;
+                    let key: String = json::from_slice(&self.data[key_start..self.pos]).ok()?;
+                    ptr.push('/');
+                    ptr.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                    self.skip_whitespace();
+                    self.expect(b':')?;
+                }
+                self.value(ptr)?;
+                ptr.truncate(len);
+
+                self.skip_whitespace();
+                match *self.data.get(self.pos)? {
+                    b',' => self.pos += 1,
+                    byte if byte == close => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return None,
+                }
+            }
+        }
+        self.entries[slot].1.range.end = self.pos;
+        Some(())
+    }
+
+    fn string(&mut self) -> Option<()> {
+        self.expect(b'"')?;
+        loop {
+            match *self.data.get(self.pos)? {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return Some(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        if self.data.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.data.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+}
+
+/// Calls `f` with the number and contents of each selected record in the contents of a
+/// file. The last records are found by reading backwards from the end, without reading
+/// the rest of the file. If the start of the file is not reached and there is no index to
+/// count the records before them, they are numbered from -1 for the last record.
+pub fn select_slice<F>(
+    data: &[u8],
+    index: Option<&LineIndex>,
+    select: Select,
+    mut f: F,
+) -> Fallible<()>
+where
+    F: FnMut(i64, &[u8]) -> Fallible<()>,
+{
+    match select {
+        Select::Tail(n) => {
+            let start = tail(data, n);
+            let before = match index {
+                _ if start == 0 => Some(0),
+                Some(index) => Some(index.count.saturating_sub(n)),
+                None => None,
+            };
+            for (i, (_, line)) in records(&data[start..]).enumerate() {
+                let number = match before {
+                    Some(before) => (before + i + 1) as i64,
+                    None => i as i64 - n as i64,
+                };
+                f(number, line)?;
+            }
+            Ok(())
+        }
+        Select::Record(k) => {
+            ensure!(k > 0, "Records are numbered from 1");
+            let (start, skip) = match index {
+                Some(index) => {
+                    ensure!(
+                        k <= index.count,
+                        "There is no record {}, the input has {} records",
+                        k,
+                        index.count
+                    );
+                    (index.offsets[(k - 1) / STRIDE] as usize, (k - 1) % STRIDE)
+                }
+                None => (0, k - 1),
+            };
+            match records(&data[start..]).nth(skip) {
+                Some((_, line)) => f(k as i64, line),
+                None => bail!("There is no record {} in the input", k),
+            }
+        }
+    }
+}
+
+/// Like `select_slice`, but reads every record from a stream.
+pub fn select_stream<R, F>(mut rdr: R, select: Select, mut f: F) -> Fallible<()>
+where
+    R: BufRead,
+    F: FnMut(i64, &[u8]) -> Fallible<()>,
+{
+    if let Select::Record(k) = select {
+        ensure!(k > 0, "Records are numbered from 1");
+    }
+    let mut last = VecDeque::new();
+    let mut number = 0;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if rdr.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        if is_blank(&buf) {
+            continue;
+        }
+        number += 1;
+        let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+        match select {
+            Select::Tail(n) => {
+                if last.len() == n {
+                    last.pop_front();
+                }
+                if n > 0 {
+                    last.push_back((number, line.to_vec()));
+                }
+            }
+            Select::Record(k) if k == number => return f(k as i64, line),
+            Select::Record(_) => (),
+        }
+    }
+    match select {
+        Select::Tail(_) => last
+            .into_iter()
+            .try_for_each(|(number, line)| f(number as i64, &line)),
+        Select::Record(k) => bail!("There is no record {}, the input has {} records", k, number),
+    }
+}
+
+/// The offset of the last `n` records, or of the first record if there are fewer.
+fn tail(data: &[u8], n: usize) -> usize {
+    let mut found = 0;
+    let mut end = data.len();
+    while found < n {
+        let start = data[..end]
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |newline| newline + 1);
+        if !is_blank(&data[start..end]) {
+            found += 1;
+        }
+        if start == 0 {
+            return 0;
+        }
+        if found == n {
+            return start;
+        }
+        end = start - 1;
+    }
+    data.len()
+}
+
+/// The offset and contents of each non-blank line.
+fn records(data: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut offset = 0;
+    data.split(|&byte| byte == b'\n')
+        .map(move |line| {
+            let start = offset;
+            offset += line.len() + 1;
+            (start, line)
+        })
+        .filter(|(_, line)| !is_blank(line))
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
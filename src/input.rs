@@ -1,31 +1,62 @@
 use std::fs;
 use std::io::{self, Cursor};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use failure::{ensure, format_err, Fallible, ResultExt};
+use failure::{bail, ensure, format_err, Error, Fallible, ResultExt};
 use grep_cli::is_readable_stdin;
 use structopt::StructOpt;
 
+use crate::index;
 use crate::io::{read_clipboard, stdin, Input};
 use crate::ser::identity;
 
 #[derive(Debug, StructOpt)]
 pub struct Opts {
-    /// Input file to read from
+    /// Input files to read from, which may be glob patterns
     #[structopt(
         name = "INPUT",
         conflicts_with = "clipboard",
         conflicts_with = "data",
         parse(from_os_str)
     )]
-    input: Option<PathBuf>,
+    input: Vec<PathBuf>,
+    /// Combine several input files into one value, so the shortening options apply to all
+    /// of them together. With `object`, each file is a field keyed by its path.
+    #[structopt(long, raw(possible_values = "Combine::NAMES"))]
+    combine: Option<Combine>,
     /// Read from clipboard
     #[structopt(long, short, conflicts_with = "data")]
     clipboard: bool,
+    /// Read a directory tree as one object, with a field for each subdirectory and each
+    /// `.json` file
+    #[structopt(
+        name = "tree",
+        long = "dir",
+        value_name = "dir",
+        conflicts_with = "INPUT",
+        conflicts_with = "clipboard",
+        conflicts_with = "data",
+        parse(from_os_str)
+    )]
+    tree: Option<PathBuf>,
+    /// The path of the entry to read from an archive. If not set, every JSON file in the
+    /// archive is read into one object.
+    #[structopt(long)]
+    entry: Option<String>,
+    /// List the files in an archive, instead of reading them.
+    #[structopt(long = "list-entries", conflicts_with = "entry")]
+    list_entries: bool,
     #[structopt(flatten)]
     data: DataOpts,
 }
 
+/// How to combine several input files.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Combine {
+    Object,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct DataOpts {
     /// The name of a file in the application's data directory to use as input.
@@ -54,6 +85,10 @@ pub struct Start {
     /// Read from clipboard
     #[structopt(long, short)]
     clipboard: bool,
+    /// Index the objects and arrays in the file, so values selected with --pointer can be
+    /// read without reading the whole file
+    #[structopt(long, short)]
+    index: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -74,7 +109,7 @@ impl Start {
             ensure!(is_readable_stdin(), "Stdin not readable");
         }
 
-        let path = opts.data.file(dir);
+        let path = opts.data.file(dir.clone());
         let mut file = match fs::OpenOptions::new()
             .write(true)
             .append(self.append)
@@ -100,6 +135,11 @@ impl Start {
         }
         .context(format!("Failed to initialize file `{}`", path.display()))?;
 
+        drop(file);
+        if self.index {
+            index::build_tree(&dir, &path)?;
+        }
+
         log::info!("Created data file `{}`.", path.display());
         Ok(())
     }
@@ -127,24 +167,95 @@ impl Clean {
     }
 }
 
+impl Opts {
+    /// The input files, with glob patterns expanded. Patterns are matched by the `glob`
+    /// crate, since the shell may not expand them.
+    pub fn paths(&self) -> Fallible<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for input in &self.input {
+            match input.to_str() {
+                Some(pattern) if is_glob(pattern) && !input.exists() => {
+                    let start = paths.len();
+                    let entries = glob::glob(pattern)
+                        .context(format!("Invalid glob pattern `{}`", pattern))?;
+                    for entry in entries {
+                        paths.push(entry?);
+                    }
+                    ensure!(paths.len() > start, "No files match `{}`", pattern);
+                }
+                _ => paths.push(input.to_owned()),
+            }
+        }
+        Ok(paths)
+    }
+
+    /// The application's data directory.
+    pub fn data_dir(&self) -> Fallible<PathBuf> {
+        self.data.dir()
+    }
+
+    /// The directory to read as a tree, if one was given.
+    pub fn tree(&self) -> Option<&Path> {
+        self.tree.as_deref()
+    }
+
+    pub fn entry(&self) -> Option<&str> {
+        self.entry.as_deref()
+    }
+
+    pub fn list_entries(&self) -> bool {
+        self.list_entries
+    }
+
+    pub fn combine(&self) -> Option<Combine> {
+        self.combine
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Reads the input when no input files are given.
 pub fn read(opts: &Opts) -> Fallible<Input> {
-    if let Some(path) = &opts.input {
-        log::debug!("Reading from input file `{}`.", path.display());
-        Input::file(path)
-    } else if opts.clipboard {
+    if opts.clipboard {
         log::debug!("Reading from clipboard.");
         Input::clipboard()
     } else if is_readable_stdin() {
         log::debug!("Reading from stdin.");
         Input::stdin()
     } else {
-        let dir = opts.data.dir()?;
-        let path = opts.data.file(dir);
+        let path = opts.data.file(opts.data.dir()?);
         log::debug!("Reading from data file `{}`.", path.display());
         Input::file(path)
     }
 }
 
+/// The data file which `read` would read from, if it reads from one.
+pub fn data_file(opts: &Opts) -> Fallible<PathBuf> {
+    if opts.clipboard {
+        bail!("Cannot watch the clipboard");
+    } else if is_readable_stdin() {
+        bail!("Cannot watch stdin");
+    }
+    Ok(opts.data.file(opts.data.dir()?))
+}
+
+impl Combine {
+    const NAMES: &'static [&'static str] = &["object"];
+}
+
+impl FromStr for Combine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "object" => Ok(Combine::Object),
+            _ => bail!("Unknown combine mode `{}`", s),
+        }
+    }
+}
+
 impl DataOpts {
     fn file(&self, dir: PathBuf) -> PathBuf {
         dir.join::<&Path>(match &self.file {
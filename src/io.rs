@@ -1,18 +1,40 @@
 use std::borrow::Cow;
-use std::fs::File;
-use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Stdin, StdinLock, Write};
+use std::fs::{File, Metadata};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Stdin, StdinLock, Write};
 use std::path::PathBuf;
 
 use clipboard::{ClipboardContext, ClipboardProvider};
-use failure::{err_msg, Fail, Fallible, ResultExt};
+use failure::{bail, err_msg, Fail, Fallible, ResultExt};
+use flate2::bufread::MultiGzDecoder;
 use grep_cli::{is_tty_stdout, StandardStream};
-use termcolor::ColorChoice;
+use memmap2::Mmap;
+use termcolor::{ColorChoice, ColorSpec, WriteColor};
+use xz2::bufread::XzDecoder;
+
+use crate::archive::Archive;
+use crate::unicode::{self, Charset};
+
+// Files smaller than this are read through a buffer, since mapping them is no faster.
+pub const MAP_THRESHOLD: u64 = 1 << 20;
 
 pub struct ReadWrapper<R> {
     rdr: R,
     label: Cow<'static, str>,
 }
 
+impl<R> ReadWrapper<R> {
+    pub fn new(rdr: R, label: impl Into<Cow<'static, str>>) -> Self {
+        ReadWrapper {
+            rdr,
+            label: label.into(),
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
 impl<R> Read for ReadWrapper<R>
 where
     R: Read,
@@ -32,6 +54,22 @@ where
     }
 }
 
+impl<R> BufRead for ReadWrapper<R>
+where
+    R: BufRead,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.rdr
+            .fill_buf()
+            .context(format!("Failed to read from {}", self.label))
+            .map_err(wrap_fail)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.rdr.consume(amt)
+    }
+}
+
 impl<R> Seek for ReadWrapper<R>
 where
     R: Seek,
@@ -44,8 +82,32 @@ where
     }
 }
 
+impl ReadWrapper<Cursor<Mapped>> {
+    /// The contents of the mapped file.
+    pub fn data(&self) -> &[u8] {
+        &self.rdr.get_ref().map
+    }
+
+    /// The metadata of the file when it was mapped.
+    pub fn metadata(&self) -> &Metadata {
+        &self.rdr.get_ref().meta
+    }
+}
+
+/// A memory-mapped file, with the metadata it had when it was mapped.
+pub struct Mapped {
+    map: Mmap,
+    meta: Metadata,
+}
+
+impl AsRef<[u8]> for Mapped {
+    fn as_ref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
 impl ReadWrapper<Stdin> {
-    pub fn lock(&self) -> ReadWrapper<StdinLock> {
+    pub fn lock(&self) -> ReadWrapper<StdinLock<'_>> {
         ReadWrapper {
             rdr: self.rdr.lock(),
             label: Cow::Borrowed("stdin"),
@@ -77,48 +139,409 @@ where
     }
 }
 
+impl<W> WriteColor for WriteWrapper<W>
+where
+    W: WriteColor,
+{
+    fn supports_color(&self) -> bool {
+        self.wtr.supports_color()
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.wtr
+            .set_color(spec)
+            .context(format!("Failed to write to {}", self.label.clone()))
+            .map_err(wrap_fail)
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.wtr
+            .reset()
+            .context(format!("Failed to write to {}", self.label.clone()))
+            .map_err(wrap_fail)
+    }
+}
+
 pub enum Input {
-    File(ReadWrapper<BufReader<File>>),
+    File(ReadWrapper<BufReader<Source>>),
+    Mmap(ReadWrapper<Cursor<Mapped>>),
     Stdin(ReadWrapper<Stdin>),
-    Buffer(Cursor<String>),
+    Buffer(ReadWrapper<Cursor<String>>),
 }
 
 impl Input {
     pub fn file(path: impl Into<PathBuf>) -> Fallible<Self> {
+        Input::open(path, true)
+    }
+
+    /// Reads from a file, which is mapped into memory if `map` is set and it is large.
+    /// Files which are expected to change while they are read should not be mapped.
+    pub fn open(path: impl Into<PathBuf>, map: bool) -> Fallible<Self> {
         let path = path.into();
         let label = format!("file `{}`", path.display());
-        Ok(Input::File(ReadWrapper {
-            rdr: BufReader::new(
-                File::open(&path).context(format!("Failed to open {}", label.clone()))?,
-            ),
-            label: Cow::Owned(label),
-        }))
+        let file = File::open(&path).context(format!("Failed to open {}", label.clone()))?;
+        Input::from_file(file, label, map)
     }
 
+    /// Reads from stdin. Compressed input and archives are copied into a temporary file,
+    /// since stdin cannot be read twice or seeked.
     pub fn stdin() -> Fallible<Self> {
-        Ok(Input::Stdin(stdin()))
+        let stdin = stdin();
+        let (compression, archive) = {
+            let mut stdin = stdin.lock();
+            let buf = stdin.fill_buf()?;
+            (Compression::detect(buf), Archive::detect(buf))
+        };
+        match compression {
+            Some(compression) => {
+                log::debug!(
+                    "Decompressing {:?} input from stdin into a temporary file.",
+                    compression
+                );
+                let mut decoder = compression.decoder(stdin.lock())?;
+                Ok(Input::spill(&mut decoder, "stdin").context("Failed to decompress stdin")?)
+            }
+            None if archive.is_some() => Input::spill(&mut stdin.lock(), "stdin"),
+            None => Ok(Input::Stdin(stdin)),
+        }
+    }
+
+    /// Copies data into a temporary file, which can be seeked and read twice.
+    pub fn spill(rdr: &mut dyn Read, label: impl Into<Cow<'static, str>>) -> Fallible<Self> {
+        let mut spill = tempfile::tempfile().context("Failed to create temporary file")?;
+        io::copy(rdr, &mut spill)?;
+        spill
+            .seek(SeekFrom::Start(0))
+            .context("Failed to seek in temporary file")?;
+        Input::from_file(spill, label, true)
+    }
+
+    /// Reads from an open file, which is mapped into memory if `map` is set and it is a
+    /// regular file of at least `MAP_THRESHOLD` bytes.
+    pub fn from_file(file: File, label: impl Into<Cow<'static, str>>, map: bool) -> Fallible<Self> {
+        // Safety: if another process truncates the file while it is mapped, reading the lost
+        // pages raises SIGBUS and the process is killed, and other changes may be seen
+        // partway through parsing. This is accepted for large files, which are rarely
+        // rewritten in place while they are viewed. Callers which expect the file to change,
+        // such as --watch, do not map it.
+        Input::from_file_with(file, label, map, |file| unsafe { Mmap::map(file) })
+    }
+
+    /// Like `from_file`, mapping the file with `mmap`. If it fails, the file is read
+    /// through a buffer instead.
+    pub fn from_file_with<F>(
+        file: File,
+        label: impl Into<Cow<'static, str>>,
+        map: bool,
+        mmap: F,
+    ) -> Fallible<Self>
+    where
+        F: FnOnce(&File) -> io::Result<Mmap>,
+    {
+        let label = label.into();
+        let meta = file
+            .metadata()
+            .ok()
+            .filter(|meta| meta.is_file() && meta.len() >= MAP_THRESHOLD);
+        if let (true, Some(meta)) = (map, meta) {
+            match mmap(&file) {
+                Ok(map) if Compression::detect(&map).is_none() => {
+                    return Ok(Input::Mmap(ReadWrapper {
+                        rdr: Cursor::new(Mapped { map, meta }),
+                        label,
+                    }));
+                }
+                Ok(_) => (),
+                Err(err) => log::debug!(
+                    "Failed to map {} into memory, falling back to buffered reads: {}.",
+                    label,
+                    err
+                ),
+            }
+        }
+
+        let mut rdr = BufReader::new(file);
+        let compression = Compression::detect(
+            rdr.fill_buf()
+                .context(format!("Failed to read from {}", label.clone()))?,
+        );
+        let source = match compression {
+            Some(compression) => {
+                log::debug!("Decompressing {:?} input.", compression);
+                Source::compressed(rdr, compression)
+                    .context(format!("Failed to read from {}", label.clone()))?
+            }
+            None => Source::Plain(rdr),
+        };
+        Ok(Input::File(ReadWrapper {
+            rdr: BufReader::new(source),
+            label,
+        }))
     }
 
     pub fn clipboard() -> Fallible<Self> {
-        Ok(Input::Buffer(Cursor::new(read_clipboard()?)))
+        Ok(Input::buffer(read_clipboard()?, "clipboard"))
+    }
+
+    pub fn buffer(buf: String, label: impl Into<Cow<'static, str>>) -> Self {
+        Input::Buffer(ReadWrapper {
+            rdr: Cursor::new(buf),
+            label: label.into(),
+        })
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Input::File(file) => file.label(),
+            Input::Mmap(map) => map.label(),
+            Input::Stdin(stdin) => stdin.label(),
+            Input::Buffer(buffer) => buffer.label(),
+        }
+    }
+
+    /// Calls `f` with the start of the input, without consuming it.
+    pub fn peek<T>(&mut self, f: impl FnOnce(&[u8]) -> T) -> Fallible<T> {
+        Ok(match self {
+            Input::File(file) => f(file.fill_buf()?),
+            Input::Mmap(map) => f(map.fill_buf()?),
+            Input::Stdin(stdin) => f(stdin.lock().fill_buf()?),
+            Input::Buffer(buffer) => f(buffer.fill_buf()?),
+        })
+    }
+
+    /// Checks whether the input is an archive. Only files are checked, since other inputs
+    /// cannot hold binary data or be seeked.
+    pub fn archive(&mut self) -> Fallible<Option<Archive>> {
+        match self {
+            Input::File(file) => Ok(Archive::detect(file.fill_buf()?)),
+            Input::Mmap(map) => Ok(Archive::detect(map.fill_buf()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Calls `f` with the path and contents of each file in an archive.
+    pub fn entries(
+        self,
+        archive: Archive,
+        f: impl FnMut(&str, &mut dyn Read) -> Fallible<()>,
+    ) -> Fallible<()> {
+        match self {
+            Input::File(file) => {
+                let label = file.label.clone();
+                archive.entries(file, f).context(format!(
+                    "Failed to read {} as a {:?} archive",
+                    label, archive
+                ))?
+            }
+            Input::Mmap(map) => {
+                let label = map.label.clone();
+                archive.entries(map.rdr, f).context(format!(
+                    "Failed to read {} as a {:?} archive",
+                    label, archive
+                ))?
+            }
+            input => bail!("Cannot read {} as an archive", input.label()),
+        }
+        Ok(())
+    }
+
+    /// Converts text input in UTF-16 or UTF-32, or with a byte order mark, to UTF-8. If
+    /// `lossy` is set, invalid sequences are replaced with U+FFFD instead of failing.
+    pub fn decode(&mut self, lossy: bool) -> Fallible<()> {
+        let label = self.label().to_owned();
+        let charset = self.peek(Charset::detect)?;
+        if charset.is_none() && !lossy {
+            return Ok(());
+        }
+
+        let mut invalid = 0;
+        self.convert(|data| {
+            let (text, count) = match charset {
+                Some(charset) => {
+                    log::debug!("Decoding input as {}.", charset);
+                    charset
+                        .decode(data, lossy)
+                        .context(format!("Failed to decode {} as {}", label, charset))?
+                }
+                None => unicode::utf8(data, lossy)?,
+            };
+            invalid = count;
+            Ok(text)
+        })?;
+        if invalid > 0 {
+            log::warn!(
+                "Replaced {} invalid sequences in {} with U+FFFD.",
+                invalid,
+                label
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of the input as binary data, and replaces it with the text returned
+    /// by `f`.
+    pub fn convert(&mut self, f: impl FnOnce(&[u8]) -> Fallible<String>) -> Fallible<()> {
+        let mut buf = Vec::new();
+        let label = match self {
+            Input::File(file) => {
+                file.read_to_end(&mut buf)?;
+                file.label.clone()
+            }
+            Input::Mmap(map) => {
+                map.read_to_end(&mut buf)?;
+                map.label.clone()
+            }
+            Input::Stdin(stdin) => {
+                stdin.read_to_end(&mut buf)?;
+                stdin.label.clone()
+            }
+            Input::Buffer(buffer) => {
+                buffer.read_to_end(&mut buf)?;
+                buffer.label.clone()
+            }
+        };
+
+        *self = Input::Buffer(ReadWrapper {
+            rdr: Cursor::new(f(&buf)?),
+            label,
+        });
+        Ok(())
+    }
+
+    /// Calls `f` with the rest of the input as binary data, which is only copied if the
+    /// input is not already in memory.
+    pub fn with_data<T>(self, f: impl FnOnce(&[u8]) -> Fallible<T>) -> Fallible<T> {
+        match self {
+            Input::Mmap(map) => f(&map.data()[map.rdr.position() as usize..]),
+            Input::Buffer(buffer) => {
+                f(&buffer.rdr.get_ref().as_bytes()[buffer.rdr.position() as usize..])
+            }
+            Input::File(mut file) => {
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                f(&buf)
+            }
+            Input::Stdin(mut stdin) => {
+                let mut buf = Vec::new();
+                stdin.read_to_end(&mut buf)?;
+                f(&buf)
+            }
+        }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_buffer(&mut self) -> Fallible<&mut Cursor<String>> {
         let mut buf = String::new();
-        match self {
-            Input::File(file) => file.read_to_string(&mut buf)?,
-            Input::Stdin(stdin) => stdin.read_to_string(&mut buf)?,
-            Input::Buffer(cursor) => return Ok(cursor),
+        let label = match self {
+            Input::File(file) => {
+                file.read_to_string(&mut buf)?;
+                file.label.clone()
+            }
+            Input::Mmap(map) => {
+                map.read_to_string(&mut buf)?;
+                map.label.clone()
+            }
+            Input::Stdin(stdin) => {
+                stdin.read_to_string(&mut buf)?;
+                stdin.label.clone()
+            }
+            Input::Buffer(buffer) => return Ok(&mut buffer.rdr),
         };
 
-        *self = Input::Buffer(Cursor::new(buf));
+        *self = Input::Buffer(ReadWrapper {
+            rdr: Cursor::new(buf),
+            label,
+        });
         match self {
-            Input::Buffer(cursor) => Ok(cursor),
+            Input::Buffer(buffer) => Ok(&mut buffer.rdr),
             _ => unreachable!(),
         }
     }
 }
 
+/// A file which is decompressed as it is read, if it is compressed.
+pub enum Source {
+    Plain(BufReader<File>),
+    Compressed {
+        file: File,
+        compression: Compression,
+        decoder: Box<dyn Read>,
+    },
+}
+
+impl Source {
+    fn compressed(rdr: BufReader<File>, compression: Compression) -> io::Result<Self> {
+        Ok(Source::Compressed {
+            file: rdr.get_ref().try_clone()?,
+            compression,
+            decoder: compression.decoder(rdr)?,
+        })
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Plain(file) => file.read(buf),
+            Source::Compressed { decoder, .. } => decoder.read(buf),
+        }
+    }
+}
+
+/// Compressed input can only be seeked from the start, by decompressing it again.
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::Plain(file) => file.seek(pos),
+            Source::Compressed {
+                file,
+                compression,
+                decoder,
+            } => match pos {
+                SeekFrom::Start(offset) => {
+                    file.seek(SeekFrom::Start(0))?;
+                    *decoder = compression.decoder(BufReader::new(file.try_clone()?))?;
+                    io::copy(&mut decoder.take(offset), &mut io::sink())
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "compressed input can only be seeked from the start",
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Detects compressed data from its magic bytes.
+    fn detect(buf: &[u8]) -> Option<Self> {
+        if buf.starts_with(b"\x1f\x8b") {
+            Some(Compression::Gzip)
+        } else if buf.starts_with(b"\x28\xb5\x2f\xfd") {
+            Some(Compression::Zstd)
+        } else if buf.starts_with(b"\xfd7zXZ\x00") {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    fn decoder<'a>(self, rdr: impl BufRead + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(rdr)),
+            Compression::Zstd => Box::new(zstd::Decoder::with_buffer(rdr)?),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(rdr)),
+        })
+    }
+}
+
 pub fn stdout() -> WriteWrapper<StandardStream> {
     let color_choice = if is_tty_stdout() {
         ColorChoice::Auto
@@ -150,5 +573,5 @@ fn wrap_std_err<T>(res: Result<T, Box<dyn std::error::Error>>) -> Fallible<T> {
 }
 
 fn wrap_fail(e: impl Fail) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, e.compat())
+    io::Error::other(e.compat())
 }
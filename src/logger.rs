@@ -5,11 +5,22 @@ use log::{Log, SetLoggerError};
 use structopt::StructOpt;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+const PAD: usize = 8;
+
 pub fn init(opts: Opts) -> Result<(), SetLoggerError> {
     log::set_max_level(opts.level_filter());
     log::set_boxed_logger(Box::new(Logger::new()))
 }
 
+/// Writes a line of the input to stderr, with a caret marking the given character.
+pub fn snippet(line: usize, text: &str, caret: usize) {
+    if log::max_level() >= log::Level::Error {
+        Logger::new()
+            .write_snippet(line, text, caret)
+            .unwrap_or_else(handle_error);
+    }
+}
+
 struct Logger {
     writer: StandardStream,
 }
@@ -62,8 +73,6 @@ impl Logger {
     }
 
     fn write(&self, lvl: log::Level, msg: impl AsRef<str>) -> io::Result<()> {
-        const PAD: usize = 8;
-
         let (prefix, color) = match lvl {
             log::Level::Trace => ("trace", Color::White),
             log::Level::Debug => ("debug", Color::Cyan),
@@ -87,6 +96,46 @@ impl Logger {
 
         Ok(())
     }
+
+    fn write_snippet(&self, line: usize, text: &str, caret: usize) -> io::Result<()> {
+        let gutter = ColorSpec::new()
+            .set_fg(Some(Color::Blue))
+            .set_bold(true)
+            .clone();
+        let width = line.to_string().len();
+
+        let mut writer = self.writer.lock();
+        writer.set_color(&gutter)?;
+        writeln!(
+            writer,
+            "{:>pad$}  {:>width$} |",
+            "",
+            "",
+            pad = PAD,
+            width = width
+        )?;
+        write!(writer, "{:>pad$}  {} | ", "", line, pad = PAD)?;
+        writer.reset()?;
+        writeln!(writer, "{}", text)?;
+        writer.set_color(&gutter)?;
+        write!(
+            writer,
+            "{:>pad$}  {:>width$} | ",
+            "",
+            "",
+            pad = PAD,
+            width = width
+        )?;
+        writer.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
+        writeln!(writer, "{:>caret$}^", "", caret = caret)?;
+        writer.reset()
+    }
+}
+
+fn handle_error(err: io::Error) {
+    if err.kind() != io::ErrorKind::BrokenPipe {
+        panic!("error writing to stderr: {}", err);
+    }
 }
 
 impl Log for Logger {
@@ -95,13 +144,9 @@ impl Log for Logger {
     }
 
     fn log(&self, record: &log::Record) {
-        if self.enabled(&record.metadata()) {
-            self.write(record.level(), &record.args().to_string())
-                .unwrap_or_else(|err| {
-                    if err.kind() != io::ErrorKind::BrokenPipe {
-                        panic!("error writing to stderr: {}", err);
-                    }
-                });
+        if self.enabled(record.metadata()) {
+            self.write(record.level(), record.args().to_string())
+                .unwrap_or_else(handle_error);
         }
     }
 
@@ -1,15 +1,39 @@
+mod archive;
+mod filter;
+mod follow;
+mod format;
+mod index;
 mod input;
 mod io;
 mod logger;
 mod ser;
+mod unicode;
 
+use std::fs::Metadata;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::Utf8Error;
+use std::thread;
+use std::time::Duration;
 
-use failure::{Error, Fallible};
+use failure::{bail, ensure, Error, Fallible, ResultExt};
+use regex::Regex;
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
+use termcolor::{Buffer, Color, ColorSpec, WriteColor};
 
+use crate::archive::Archive;
+use crate::filter::Filter;
+use crate::follow::{Follower, Watcher};
+use crate::format::Format;
+use crate::index::Select;
+use crate::input::Combine;
 use crate::io::Input;
+use crate::ser::Foreign;
+
+/// How often to check the input for changes with the --follow and --watch options.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 /// json-view is a utility for viewing JSON files in the terminal.
 #[derive(Debug, StructOpt)]
@@ -19,12 +43,76 @@ pub struct Opts {
     #[structopt(flatten)]
     input: input::Opts,
     #[structopt(flatten)]
+    format: format::Opts,
+    #[structopt(flatten)]
     logger: logger::Opts,
     #[structopt(flatten)]
     ser: ser::Opts,
     /// A pointer to select a value to output.
     #[structopt(long, short)]
     pointer: Option<String>,
+    /// Only output array elements matching a predicate of the form `<pointer> <op> <value>`,
+    /// where `op` is one of `==`, `!=`, `<`, `>`, `=~` or `exists`.
+    #[structopt(long = "where", short = "w")]
+    filter: Option<Filter>,
+    /// Render as much of the input as can be parsed, instead of failing on invalid or
+    /// truncated JSON.
+    #[structopt(long)]
+    lenient: bool,
+    /// Replace invalid UTF-8 in the input with U+FFFD, instead of failing.
+    #[structopt(long)]
+    lossy: bool,
+    /// Read the input as newline-delimited JSON, writing each record separately. This is
+    /// detected automatically if the first line of the input is a complete value. With
+    /// the --where option, whole records are filtered, unless --slurp is used. Records
+    /// without a value at --pointer are skipped, but it is an error if every record is.
+    #[structopt(long, conflicts_with = "lenient")]
+    lines: bool,
+    /// Find the first JSON object or array in each line of the input, such as in log
+    /// lines, and write it under the rest of the line.
+    #[structopt(long, conflicts_with = "lines", conflicts_with = "lenient")]
+    extract: bool,
+    /// A regex which the value must follow with the --extract option.
+    #[structopt(long, requires = "extract")]
+    anchor: Option<Regex>,
+    /// Skip lines without a value with the --extract option, instead of writing them
+    /// unchanged.
+    #[structopt(long = "skip-plain", requires = "extract")]
+    skip_plain: bool,
+    /// Write only the last N records of newline-delimited JSON. Uncompressed files are
+    /// read backwards from the end, so unless they have been indexed by --record, their
+    /// records are numbered from -1 for the last record.
+    #[structopt(
+        long,
+        value_name = "N",
+        conflicts_with = "record",
+        conflicts_with = "slurp"
+    )]
+    tail: Option<usize>,
+    /// Write only record K of newline-delimited JSON, counting from 1. For uncompressed
+    /// files, an index of the records is kept in the data directory, and rebuilt when the
+    /// file changes.
+    #[structopt(long, value_name = "K", conflicts_with = "slurp")]
+    record: Option<usize>,
+    /// Wait for records to be appended to a newline-delimited JSON file, and write each
+    /// one as it arrives, like `tail -f`.
+    #[structopt(
+        long,
+        requires = "INPUT",
+        conflicts_with = "extract",
+        conflicts_with = "slurp",
+        conflicts_with = "combine"
+    )]
+    follow: bool,
+    /// Watch the input file for changes, clearing the screen and writing it again each
+    /// time. If it cannot be parsed, the last good output is kept.
+    #[structopt(
+        long,
+        conflicts_with = "follow",
+        conflicts_with = "combine",
+        conflicts_with = "tree"
+    )]
+    watch: bool,
     #[structopt(subcommand)]
     cmd: Option<Command>,
 }
@@ -35,6 +123,21 @@ pub enum Command {
     Start(input::Start),
     #[structopt(name = "clean", about = "Remove all data files")]
     Clean(input::Clean),
+    #[structopt(
+        name = "unflatten",
+        about = "Rebuild JSON from the output of --flatten"
+    )]
+    Unflatten(ser::Unflatten),
+}
+
+impl Opts {
+    fn select(&self) -> Option<Select> {
+        match (self.tail, self.record) {
+            (Some(n), _) => Some(Select::Tail(n)),
+            (_, Some(k)) => Some(Select::Record(k)),
+            _ => None,
+        }
+    }
 }
 
 fn main() {
@@ -44,6 +147,13 @@ fn main() {
 
     if let Err(err) = run(&opts) {
         log::error!("{}", fmt_error(&err));
+        if let Some(err) = err
+            .iter_chain()
+            .find_map(|cause| cause.downcast_ref::<ser::ParseError>())
+        {
+            logger::snippet(err.line(), err.snippet(), err.caret());
+        }
+        process::exit(1);
     }
 }
 
@@ -52,25 +162,513 @@ fn run(opts: &Opts) -> Fallible<()> {
         return match cmd {
             Command::Start(start) => start.run(&opts.input),
             Command::Clean(clean) => clean.run(&opts.input),
+            Command::Unflatten(unflatten) => unflatten.run(opts.ser),
         };
     }
 
     let mut stdout = io::stdout();
-    let mut input = input::read(&opts.input)?;
-    let result = if let Some(ptr) = &opts.pointer {
+    let paths = opts.input.paths()?;
+    if opts.follow {
+        return follow(opts, &paths, &mut stdout);
+    } else if opts.watch {
+        return watch(opts, &paths, &mut stdout);
+    }
+    if let Some(dir) = opts.input.tree() {
+        let label = format!("directory `{}`", dir.display());
+        let input = Input::buffer(json::to_string(&read_dir(opts, dir)?)?, label);
+        render(opts, input, Format::Json, &mut stdout)?;
+    } else if paths.is_empty() {
+        let input = input::read(&opts.input)?;
+        let path = input::data_file(&opts.input).ok();
+        view(opts, input, path.as_deref(), &mut stdout)?;
+    } else if let Some(Combine::Object) = opts.input.combine() {
+        let mut combined = json::Map::new();
+        for path in &paths {
+            combined.insert(path.display().to_string(), read_file(opts, path)?);
+        }
+        let input = Input::buffer(json::to_string(&combined)?, "combined input");
+        render(opts, input, Format::Json, &mut stdout)?;
+    } else {
+        for (index, path) in paths.iter().enumerate() {
+            log::debug!("Reading from input file `{}`.", path.display());
+            if paths.len() > 1 {
+                if index > 0 {
+                    writeln!(stdout)?;
+                }
+                ser::write_header(&mut stdout, &path.display().to_string())?;
+            }
+            view(opts, Input::file(path)?, Some(path), &mut stdout)?;
+        }
+    }
+
+    stdout.flush().map_err(Into::into)
+}
+
+/// Reads a file into a single value.
+fn read_file(opts: &Opts, path: &Path) -> Fallible<json::Value> {
+    log::debug!("Reading from input file `{}`.", path.display());
+    read_input(opts, Input::file(path)?, Some(path))
+}
+
+fn read_input(opts: &Opts, mut input: Input, path: Option<&Path>) -> Fallible<json::Value> {
+    if let Some(archive) = input.archive()? {
+        return read_archive(opts, input, archive);
+    }
+
+    let format = prepare(opts, &mut input, path)?;
+    let label = input.label().to_owned();
+    if let Some(foreign) = format.foreign(opts.format.bytes()) {
+        return input
+            .with_data(|data| foreign.value(data))
+            .map_err(|err| label_conversion_error(err, foreign, &label));
+    }
+    match input {
+        Input::File(file) => ser::read_value(file),
+        Input::Mmap(map) => ser::read_value(map),
+        Input::Buffer(cursor) => ser::read_value(cursor),
+        Input::Stdin(stdin) => ser::read_value(stdin.lock()),
+    }
+    .map_err(|err| label_parse_error(err, &label))
+}
+
+/// Reads the JSON files in an archive into an object, nested by the directories in their
+/// paths.
+fn read_archive(opts: &Opts, input: Input, archive: Archive) -> Fallible<json::Value> {
+    let label = input.label().to_owned();
+    let mut object = json::Map::new();
+    input.entries(archive, |name, rdr| {
+        if !name.ends_with(".json") {
+            return Ok(());
+        }
+
+        let entry = Input::spill(rdr, format!("entry `{}` in {}", name, label))?;
+        let value = read_input(opts, entry, Some(Path::new(name)))?;
+        let mut parent = &mut object;
+        let mut components = name.split('/').filter(|component| !component.is_empty());
+        let file = components.next_back().unwrap_or(name);
+        for component in components {
+            let dir = parent
+                .entry(component)
+                .or_insert_with(|| json::Value::Object(json::Map::new()));
+            parent = match dir {
+                json::Value::Object(dir) => dir,
+                _ => bail!("Entry `{}` in {} is inside a file", name, label),
+            };
+        }
+        parent.insert(file.to_owned(), value);
+        Ok(())
+    })?;
+    Ok(json::Value::Object(object))
+}
+
+/// Reads a directory tree into an object, with a field for each subdirectory and each
+/// JSON file.
+fn read_dir(opts: &Opts, dir: &Path) -> Fallible<json::Value> {
+    let mut object = json::Map::new();
+    let mut entries = dir
+        .read_dir()
+        .and_then(|entries| {
+            entries
+                .map(|entry| {
+                    let entry = entry?;
+                    Ok((entry.path(), entry.file_type()?))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .context(format!("Failed to read directory `{}`", dir.display()))?;
+    // The order of directory entries depends on the file system.
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (path, file_type) in entries {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        if file_type.is_dir() {
+            object.insert(name, read_dir(opts, &path)?);
+        } else if file_type.is_symlink() && path.is_dir() {
+            // A link to a directory could lead back to one of its parents.
+            log::warn!(
+                "Skipping `{}`, which is a link to a directory.",
+                path.display()
+            );
+        } else if name.ends_with(".json") {
+            object.insert(name, read_file(opts, &path)?);
+        }
+    }
+    Ok(json::Value::Object(object))
+}
+
+/// Writes the input, or an entry from it if it is an archive.
+fn view<W>(opts: &Opts, mut input: Input, path: Option<&Path>, stdout: &mut W) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    if let Some(select) = opts.select() {
+        return records(opts, input, path, select, stdout);
+    }
+
+    let archive = match input.archive()? {
+        Some(archive) => archive,
+        None => {
+            if let Some(entry) = opts.input.entry() {
+                bail!(
+                    "Cannot read entry `{}` from {}, which is not an archive",
+                    entry,
+                    input.label()
+                );
+            }
+            if opts.input.list_entries() {
+                bail!(
+                    "Cannot list the entries of {}, which is not an archive",
+                    input.label()
+                );
+            }
+            let format = prepare(opts, &mut input, path)?;
+            if let (Some(path), Some(ptr), Input::Mmap(map)) = (path, &opts.pointer, &input) {
+                let indexable = format == Format::Json && !opts.lines && !opts.extract;
+                if indexable
+                    && !opts.ser.slurp()
+                    && project_indexed(opts, path, map.data(), map.metadata(), ptr, stdout)?
+                {
+                    return Ok(());
+                }
+            }
+            return render(opts, input, format, stdout);
+        }
+    };
+    log::debug!("Reading input as a {:?} archive.", archive);
+
+    let label = input.label().to_owned();
+    if opts.input.list_entries() {
+        input.entries(archive, |name, _| {
+            writeln!(stdout, "{}", name)?;
+            Ok(())
+        })
+    } else if let Some(entry) = opts.input.entry() {
+        let entry = archive::normalize(entry);
+        let mut found = None;
+        input.entries(archive, |name, rdr| {
+            if name == entry {
+                found = Some(Input::spill(rdr, format!("entry `{}` in {}", name, label))?);
+            }
+            Ok(())
+        })?;
+        let mut input = match found {
+            Some(input) => input,
+            None => bail!("No entry `{}` in {}", entry, label),
+        };
+        let format = prepare(opts, &mut input, Some(Path::new(entry)))?;
+        render(opts, input, format, stdout)
+    } else {
+        let value = read_archive(opts, input, archive)?;
+        render(
+            opts,
+            Input::buffer(json::to_string(&value)?, label),
+            Format::Json,
+            stdout,
+        )
+    }
+}
+
+/// Converts the input to JSON if it is in another format, returning the original format.
+fn prepare(opts: &Opts, input: &mut Input, path: Option<&Path>) -> Fallible<Format> {
+    let format = match opts.format.format(path) {
+        Some(format) => format,
+        None => input.peek(Format::sniff)?.unwrap_or(Format::Json),
+    };
+    log::debug!("Reading input as {:?}.", format);
+    if format != Format::Cbor && format != Format::MsgPack {
+        input.decode(opts.lossy)?;
+    }
+    match format {
+        Format::Json => (),
+        Format::Jsonc => ser::relax(input.to_buffer()?, false),
+        Format::Json5 => ser::relax(input.to_buffer()?, true),
+        // Other formats are transcoded as they are written.
+        _ => return Ok(format),
+    }
+    if opts.lenient {
+        ser::repair(input.to_buffer()?)?;
+    }
+    Ok(format)
+}
+
+fn render<W>(opts: &Opts, mut input: Input, format: Format, stdout: &mut W) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    let label = input.label().to_owned();
+    let ptr = opts.pointer.as_ref().map_or("", String::as_str);
+    let filter = opts.filter.as_ref();
+    let lines =
+        opts.lines || (format == Format::Json && !opts.lenient && input.peek(ser::is_lines)?);
+    let anchor = opts.anchor.as_ref();
+    if let Some(foreign) = format.foreign(opts.format.bytes()) {
+        ensure!(
+            !opts.extract,
+            "Cannot extract values from {} input",
+            foreign.name()
+        );
+        return input
+            .with_data(|data| {
+                ser::convert(opts.ser, foreign, data, ptr, filter, opts.lines, stdout)
+            })
+            .map_err(|err| label_conversion_error(err, foreign, &label));
+    }
+
+    let result = if opts.extract {
+        let skip = opts.skip_plain;
+        match input {
+            Input::File(file) => ser::extract(opts.ser, anchor, skip, ptr, filter, file, stdout),
+            Input::Mmap(map) => ser::extract(opts.ser, anchor, skip, ptr, filter, map, stdout),
+            Input::Buffer(cursor) => {
+                ser::extract(opts.ser, anchor, skip, ptr, filter, cursor, stdout)
+            }
+            Input::Stdin(stdin) => {
+                ser::extract(opts.ser, anchor, skip, ptr, filter, stdin.lock(), stdout)
+            }
+        }
+    } else if lines && !opts.ser.slurp() {
+        match input {
+            Input::File(file) => ser::lines(opts.ser, ptr, filter, file, stdout),
+            Input::Mmap(map) => ser::lines(opts.ser, ptr, filter, map, stdout),
+            Input::Buffer(cursor) => ser::lines(opts.ser, ptr, filter, cursor, stdout),
+            Input::Stdin(stdin) => ser::lines(opts.ser, ptr, filter, stdin.lock(), stdout),
+        }
+    } else if opts.pointer.is_some() || opts.filter.is_some() {
         match input {
-            Input::File(file) => ser::project(opts.ser, ptr, file, &mut stdout),
-            Input::Buffer(cursor) => ser::project(opts.ser, ptr, cursor, &mut stdout),
-            Input::Stdin(stdin) => ser::project(opts.ser, ptr, stdin.lock(), &mut stdout),
+            Input::File(file) => ser::project(opts.ser, ptr, filter, file, stdout),
+            Input::Mmap(map) => ser::project(opts.ser, ptr, filter, map, stdout),
+            Input::Buffer(cursor) => ser::project(opts.ser, ptr, filter, cursor, stdout),
+            Input::Stdin(stdin) => ser::project(opts.ser, ptr, filter, stdin.lock(), stdout),
         }
     } else {
         match input {
-            Input::File(file) => ser::shorten(opts.ser, file, &mut stdout),
-            _ => ser::shorten(opts.ser, input.to_buffer()?, &mut stdout),
+            Input::File(file) => ser::shorten(opts.ser, file, stdout),
+            Input::Mmap(map) => ser::shorten_slice(opts.ser, map.data(), stdout),
+            _ => ser::shorten(opts.ser, input.to_buffer()?, stdout),
+        }
+    };
+
+    result.map_err(|err| label_parse_error(err, &label))
+}
+
+/// Writes each record appended to a file, until interrupted.
+fn follow<W>(opts: &Opts, paths: &[PathBuf], stdout: &mut W) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    let path = match paths {
+        [path] => path,
+        _ => bail!("Only one file can be followed"),
+    };
+    let ptr = opts.pointer.as_ref().map_or("", String::as_str);
+    let filter = opts.filter.as_ref();
+    let mut follower = Follower::new(path)?;
+    let mut number = 0;
+    loop {
+        follower.poll(|line| {
+            number += 1;
+            match json::from_slice(line) {
+                Ok(value) => {
+                    ser::write_record(opts.ser, number, ptr, filter, &value, &mut *stdout)?;
+                    Ok(())
+                }
+                Err(err) => {
+                    log::warn!("Skipping record {}: {}", number, err);
+                    Ok(())
+                }
+            }
+        })?;
+        stdout.flush()?;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Writes the input each time it changes, until interrupted.
+fn watch<W>(opts: &Opts, paths: &[PathBuf], stdout: &mut W) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    let mut watcher = match paths {
+        [] => Watcher::new(input::data_file(&opts.input)?),
+        [path] => Watcher::new(path),
+        _ => bail!("Only one file can be watched"),
+    };
+    let mut last = Vec::new();
+    loop {
+        if watcher.changed() {
+            redraw(opts, watcher.path(), &mut last, stdout)?;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Clears the screen and writes the file at `path`. If it cannot be read, `last`, the
+/// output of the last successful call, is written again followed by the error, so a
+/// half-written file does not clear the screen. Before any call has succeeded, only the
+/// error is written.
+fn redraw<W>(opts: &Opts, path: &Path, last: &mut Vec<u8>, stdout: &mut W) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    let mut buf = if stdout.supports_color() {
+        Buffer::ansi()
+    } else {
+        Buffer::no_color()
+    };
+    let result = Input::open(path, false).and_then(|input| view(opts, input, Some(path), &mut buf));
+
+    write!(stdout, "\x1b[2J\x1b[H")?;
+    if result.is_ok() {
+        *last = buf.into_inner();
+    }
+    stdout.write_all(last)?;
+    if !last.is_empty() && !last.ends_with(b"\n") {
+        writeln!(stdout)?;
+    }
+    if let Err(err) = result {
+        let causes: Vec<_> = err.iter_chain().map(ToString::to_string).collect();
+        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
+        write!(stdout, "// {}", causes.join(": "))?;
+        stdout.reset()?;
+        writeln!(stdout)?;
+    }
+    Ok(stdout.flush()?)
+}
+
+/// Writes the value at `ptr` in a file using its tree index, reading only the smallest
+/// indexed object or array containing it. Returns `false` if the file has no current index
+/// or the value is not found, so the whole file is read instead.
+fn project_indexed<W>(
+    opts: &Opts,
+    path: &Path,
+    data: &[u8],
+    meta: &Metadata,
+    ptr: &str,
+    stdout: &mut W,
+) -> Fallible<bool>
+where
+    W: WriteColor,
+{
+    let index = match opts
+        .input
+        .data_dir()
+        .ok()
+        .and_then(|dir| index::load_tree(&dir, path, meta))
+    {
+        Some(index) => index,
+        None => return Ok(false),
+    };
+    let mut base = ptr;
+    let container = loop {
+        if let Some(container) = index.get(base) {
+            break container;
         }
+        match base.rfind('/') {
+            Some(end) => base = &base[..end],
+            None => return Ok(false),
+        }
+    };
+    let arrays: Vec<_> = base
+        .match_indices('/')
+        .map(|(end, _)| index.get(&base[..end]).is_some_and(|parent| parent.array))
+        .collect();
+    let value = match container.slice(data).map(json::from_slice) {
+        Some(Ok(value)) => value,
+        _ => return Ok(false),
     };
+    log::debug!(
+        "Reading `{}` using the index of `{}`.",
+        base,
+        path.display()
+    );
+    let filter = opts.filter.as_ref();
+    ser::project_subtree(
+        opts.ser,
+        base,
+        &arrays,
+        value,
+        &ptr[base.len()..],
+        filter,
+        stdout,
+    )
+}
 
-    result.and(stdout.flush().map_err(Into::into))
+/// Writes the records chosen by the --tail or --record option.
+fn records<W>(
+    opts: &Opts,
+    input: Input,
+    path: Option<&Path>,
+    select: Select,
+    stdout: &mut W,
+) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    let label = input.label().to_owned();
+    let ptr = opts.pointer.as_ref().map_or("", String::as_str);
+    let filter = opts.filter.as_ref();
+    let mut misses = ser::Misses::default();
+    let write = |number: i64, line: &[u8]| {
+        let value = json::from_slice(line)
+            .context(format!("Failed to parse record {} of {}", number, label))?;
+        misses.add(ser::write_record(
+            opts.ser,
+            number,
+            ptr,
+            filter,
+            &value,
+            &mut *stdout,
+        )?);
+        Ok(())
+    };
+    let result = match input {
+        Input::Mmap(map) => {
+            let index = match (select, path) {
+                (Select::Record(_), Some(path)) => Some(index::open(
+                    &opts.input.data_dir()?,
+                    path,
+                    map.data(),
+                    map.metadata(),
+                )?),
+                (Select::Tail(_), Some(path)) => opts
+                    .input
+                    .data_dir()
+                    .ok()
+                    .and_then(|dir| index::load(&dir, path, map.metadata())),
+                _ => None,
+            };
+            index::select_slice(map.data(), index.as_ref(), select, write)
+        }
+        Input::File(file) => index::select_stream(file, select, write),
+        Input::Buffer(cursor) => index::select_stream(cursor, select, write),
+        Input::Stdin(stdin) => index::select_stream(stdin.lock(), select, write),
+    };
+    result?;
+    misses.check(ptr)
+}
+
+fn label_parse_error(err: Error, label: &str) -> Error {
+    if err.downcast_ref::<ser::ParseError>().is_some() {
+        err.context(format!("Failed to parse {}", label)).into()
+    } else {
+        err
+    }
+}
+
+/// Labels errors in the syntax of input which is transcoded from another format.
+fn label_conversion_error(err: Error, foreign: Foreign, label: &str) -> Error {
+    let syntax = err
+        .downcast_ref::<json::Error>()
+        .is_some_and(|err| !err.is_io())
+        || err.downcast_ref::<Utf8Error>().is_some();
+    if syntax {
+        err.context(format!("Failed to parse {} as {}", label, foreign.name()))
+            .into()
+    } else {
+        err
+    }
 }
 
 fn fmt_error(err: &Error) -> String {
@@ -0,0 +1,105 @@
+use std::io::Cursor;
+use std::str;
+
+use failure::Fallible;
+use serde::ser::Serialize;
+
+use super::transcode::{Encoding, Transcoder};
+
+/// A format other than JSON. Its documents are transcoded straight into the serializer
+/// which writes them, without being written as JSON text first.
+#[derive(Copy, Clone, Debug)]
+pub enum Foreign {
+    Yaml,
+    Toml,
+    /// CBOR, with the encoding used to write byte strings.
+    Cbor(Encoding),
+    /// MessagePack, with the encoding used to write byte strings.
+    MsgPack(Encoding),
+}
+
+/// Receives each document in foreign input, as a value which transcodes it when it is
+/// serialized. Each document can only be serialized once.
+pub trait Visit {
+    fn document<T>(&mut self, index: usize, doc: &T) -> Fallible<()>
+    where
+        T: Serialize;
+}
+
+impl Foreign {
+    pub fn name(self) -> &'static str {
+        match self {
+            Foreign::Yaml => "YAML",
+            Foreign::Toml => "TOML",
+            Foreign::Cbor(_) => "CBOR",
+            Foreign::MsgPack(_) => "MessagePack",
+        }
+    }
+
+    /// Calls `visit` with each document in `data`. YAML and CBOR input may hold a
+    /// sequence of documents, and MessagePack a sequence of values.
+    pub fn documents<V>(self, data: &[u8], visit: &mut V) -> Fallible<()>
+    where
+        V: Visit,
+    {
+        match self {
+            Foreign::Yaml => {
+                let text = str::from_utf8(data)?;
+                for (index, doc) in serde_yaml::Deserializer::from_str(text).enumerate() {
+                    visit.document(index, &Transcoder::new(doc, Encoding::default()))?;
+                }
+            }
+            Foreign::Toml => {
+                let mut de = toml::Deserializer::new(str::from_utf8(data)?);
+                visit.document(0, &Transcoder::new(&mut de, Encoding::default()))?;
+            }
+            Foreign::Cbor(bytes) => {
+                let mut de = serde_cbor::Deserializer::from_slice(data);
+                let mut index = 0;
+                while de.byte_offset() < data.len() {
+                    visit.document(index, &Transcoder::new(&mut de, bytes))?;
+                    index += 1;
+                }
+            }
+            Foreign::MsgPack(bytes) => {
+                let mut de = rmp_serde::Deserializer::new(Cursor::new(data));
+                let mut index = 0;
+                while de.position() < data.len() as u64 {
+                    visit.document(index, &Transcoder::new(&mut de, bytes))?;
+                    index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads each document in `data` into a value.
+    pub fn values(self, data: &[u8]) -> Fallible<Vec<json::Value>> {
+        struct Values(Vec<json::Value>);
+
+        impl Visit for Values {
+            fn document<T>(&mut self, _: usize, doc: &T) -> Fallible<()>
+            where
+                T: Serialize,
+            {
+                self.0.push(json::to_value(doc)?);
+                Ok(())
+            }
+        }
+
+        let mut values = Values(Vec::new());
+        self.documents(data, &mut values)?;
+        Ok(values.0)
+    }
+
+    /// Reads `data` into a single value, wrapping it in an array if it contains several
+    /// documents.
+    pub fn value(self, data: &[u8]) -> Fallible<json::Value> {
+        let mut values = self.values(data)?;
+        Ok(if values.len() == 1 {
+            values.pop().unwrap()
+        } else {
+            json::Value::Array(values)
+        })
+    }
+}
@@ -7,11 +7,12 @@ use failure::Fallible;
 use json::ser::Formatter;
 
 use super::exclude::ExcludeSet;
+use super::expand::{Expand, Mark};
 use super::Opts;
 
-pub fn count<'de, F>(opts: Opts, f: F) -> Fallible<ExcludeSet>
+pub fn count<F>(opts: Opts, f: F) -> Fallible<ExcludeSet>
 where
-    F: FnOnce(&mut json::Serializer<Sink, &mut Counter>) -> Fallible<()>,
+    F: FnOnce(&mut json::Serializer<Sink, Expand<&mut Counter>>) -> Fallible<()>,
 {
     let mut counter = Counter {
         opts,
@@ -22,8 +23,8 @@ where
         objects: BinaryHeap::new(),
     };
 
-    let mut ser = json::Serializer::with_formatter(io::sink(), &mut counter);
-    f(&mut ser)?;
+    let expand = Expand::new(&mut counter, opts.expand_strings);
+    f(&mut json::Serializer::with_formatter(io::sink(), expand))?;
 
     let mut excludes = ExcludeSet::new();
     if let Some(max_length) = opts.max_length {
@@ -153,6 +154,12 @@ impl Formatter for &'_ mut Counter {
     }
 }
 
+impl Mark for &'_ mut Counter {
+    fn mark<W: ?Sized>(&mut self, _: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct HalfObject {
     start: u32,
@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::io::Read;
+
+use failure::Fallible;
+use json::de::{self, Deserializer, IoRead, SliceRead};
+use serde::de::Deserialize;
+use serde::ser::{Error as _, Serialize, SerializeSeq, Serializer};
+
+use super::track::{self, Tracker};
+use super::transcode::{Encoding, Transcoder};
+
+type Reader<'a, R> = IoRead<&'a mut Tracker<R>>;
+
+/// Calls `f` with each of a sequence of concatenated JSON documents, or once with all of
+/// them wrapped in an array if `slurp` is set.
+pub fn documents<R, F>(slurp: bool, rdr: R, f: F) -> Fallible<()>
+where
+    R: Read,
+    F: FnMut(usize, Document<'_, Reader<'_, R>>) -> Fallible<()>,
+{
+    let mut rdr = Tracker::new(rdr);
+    let result = each(slurp, &mut Deserializer::from_reader(&mut rdr), f);
+    result.map_err(|err| match err.downcast::<json::Error>() {
+        Ok(err) => rdr.error(err),
+        Err(err) => err,
+    })
+}
+
+/// Like `documents`, but parses the input directly from a byte slice.
+pub fn slice_documents<'a, F>(slurp: bool, data: &'a [u8], f: F) -> Fallible<()>
+where
+    F: FnMut(usize, Document<'_, SliceRead<'a>>) -> Fallible<()>,
+{
+    let result = each(slurp, &mut Deserializer::from_slice(data), f);
+    result.map_err(|err| match err.downcast::<json::Error>() {
+        Ok(err) => track::slice_error(data, err),
+        Err(err) => err,
+    })
+}
+
+fn each<'de, R, F>(slurp: bool, de: &mut Deserializer<R>, mut f: F) -> Fallible<()>
+where
+    R: de::Read<'de>,
+    F: FnMut(usize, Document<'_, R>) -> Fallible<()>,
+{
+    let mut index = 0;
+    loop {
+        let doc = Document {
+            de: RefCell::new(&mut *de),
+            slurp,
+        };
+        f(index, doc)?;
+        match more(de) {
+            Ok(true) if !slurp => index += 1,
+            Ok(_) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Checks whether there is another document after the one just read.
+fn more<'de, R>(de: &mut Deserializer<R>) -> Result<bool, json::Error>
+where
+    R: de::Read<'de>,
+{
+    match de.end() {
+        Ok(()) => Ok(false),
+        Err(err) if err.is_syntax() => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+/// A document in the input, which can be transcoded or read into a value once.
+pub struct Document<'a, R> {
+    de: RefCell<&'a mut Deserializer<R>>,
+    slurp: bool,
+}
+
+impl<'de, R> Document<'_, R>
+where
+    R: de::Read<'de>,
+{
+    pub fn value(self) -> Result<json::Value, json::Error> {
+        let de = self.de.into_inner();
+        if self.slurp {
+            let mut values = Vec::new();
+            while more(de)? {
+                values.push(json::Value::deserialize(&mut *de)?);
+            }
+            Ok(json::Value::Array(values))
+        } else {
+            json::Value::deserialize(de)
+        }
+    }
+}
+
+impl<'de, R> Serialize for Document<'_, R>
+where
+    R: de::Read<'de>,
+{
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut de = self.de.borrow_mut();
+        if self.slurp {
+            let mut seq = ser.serialize_seq(None)?;
+            while more(&mut **de).map_err(S::Error::custom)? {
+                seq.serialize_element(&Transcoder::new(&mut **de, Encoding::default()))?;
+            }
+            seq.end()
+        } else {
+            Transcoder::new(&mut **de, Encoding::default()).serialize(ser)
+        }
+    }
+}
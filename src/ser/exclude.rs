@@ -5,36 +5,53 @@ use std::ops::Range;
 use failure::Fallible;
 use json::ser::{CharEscape, Formatter, PrettyFormatter};
 
-pub fn write<'de, F, W>(excludes: ExcludeSet, writer: W, f: F) -> Fallible<()>
+use super::expand::{Expand, Mark};
+
+pub fn write<F, W>(
+    excludes: ExcludeSet,
+    indices: &[usize],
+    expand: bool,
+    writer: W,
+    f: F,
+) -> Fallible<()>
 where
-    F: FnOnce(&mut json::Serializer<W, Excluder>) -> Fallible<()>,
+    F: FnOnce(&mut json::Serializer<W, Expand<Excluder>>) -> Fallible<()>,
     W: Write,
 {
     let excluder = Excluder {
         excludes,
+        indices,
         position: 0,
         depth: 0,
+        nesting: 0,
+        element: 0,
         pretty: PrettyFormatter::new(),
     };
-    let mut ser = json::Serializer::with_formatter(writer, excluder);
+    let mut ser = json::Serializer::with_formatter(writer, Expand::new(excluder, expand));
     f(&mut ser)
 }
 
-pub struct Excluder {
+pub struct Excluder<'a> {
     excludes: ExcludeSet,
+    // The original indices of the elements of the top-level array, if it was filtered.
+    indices: &'a [usize],
     position: u32,
     depth: u32,
+    // The number of objects currently open.
+    nesting: u32,
+    // The number of elements of the top-level array seen so far.
+    element: usize,
     pretty: PrettyFormatter<'static>,
 }
 
-impl Excluder {
+impl Excluder<'_> {
     fn writing(&self) -> bool {
         self.depth == 0
     }
 
-    fn begin<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn begin<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         if let Some(length) = self.excludes.get(self.position) {
             if self.writing() {
@@ -43,14 +60,31 @@ impl Excluder {
             self.depth += 1;
         }
         self.position += 1;
+        self.nesting += 1;
         Ok(())
     }
 
     fn end(&mut self) {
-        if let Some(_) = self.excludes.get(self.position) {
+        if self.excludes.get(self.position).is_some() {
             self.depth -= 1;
         }
         self.position += 1;
+        self.nesting -= 1;
+    }
+
+    fn annotate<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if self.nesting == 1 {
+            if let Some(index) = self.indices.get(self.element) {
+                if self.writing() {
+                    write!(writer, "/* {} */ ", index)?;
+                }
+            }
+            self.element += 1;
+        }
+        Ok(())
     }
 
     fn delegate(
@@ -65,212 +99,221 @@ impl Excluder {
     }
 }
 
-impl Formatter for Excluder {
-    fn write_null<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+impl Formatter for Excluder<'_> {
+    fn write_null<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_null(writer))
     }
 
-    fn write_bool<W: ?Sized>(&mut self, writer: &mut W, value: bool) -> io::Result<()>
+    fn write_bool<W>(&mut self, writer: &mut W, value: bool) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_bool(writer, value))
     }
 
-    fn write_i8<W: ?Sized>(&mut self, writer: &mut W, value: i8) -> io::Result<()>
+    fn write_i8<W>(&mut self, writer: &mut W, value: i8) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_i8(writer, value))
     }
 
-    fn write_i16<W: ?Sized>(&mut self, writer: &mut W, value: i16) -> io::Result<()>
+    fn write_i16<W>(&mut self, writer: &mut W, value: i16) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_i16(writer, value))
     }
 
-    fn write_i32<W: ?Sized>(&mut self, writer: &mut W, value: i32) -> io::Result<()>
+    fn write_i32<W>(&mut self, writer: &mut W, value: i32) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_i32(writer, value))
     }
 
-    fn write_i64<W: ?Sized>(&mut self, writer: &mut W, value: i64) -> io::Result<()>
+    fn write_i64<W>(&mut self, writer: &mut W, value: i64) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_i64(writer, value))
     }
 
-    fn write_u8<W: ?Sized>(&mut self, writer: &mut W, value: u8) -> io::Result<()>
+    fn write_u8<W>(&mut self, writer: &mut W, value: u8) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_u8(writer, value))
     }
 
-    fn write_u16<W: ?Sized>(&mut self, writer: &mut W, value: u16) -> io::Result<()>
+    fn write_u16<W>(&mut self, writer: &mut W, value: u16) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_u16(writer, value))
     }
 
-    fn write_u32<W: ?Sized>(&mut self, writer: &mut W, value: u32) -> io::Result<()>
+    fn write_u32<W>(&mut self, writer: &mut W, value: u32) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_u32(writer, value))
     }
 
-    fn write_u64<W: ?Sized>(&mut self, writer: &mut W, value: u64) -> io::Result<()>
+    fn write_u64<W>(&mut self, writer: &mut W, value: u64) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_u64(writer, value))
     }
 
-    fn write_f32<W: ?Sized>(&mut self, writer: &mut W, value: f32) -> io::Result<()>
+    fn write_f32<W>(&mut self, writer: &mut W, value: f32) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_f32(writer, value))
     }
 
-    fn write_f64<W: ?Sized>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_f64(writer, value))
     }
 
-    fn write_number_str<W: ?Sized>(&mut self, writer: &mut W, value: &str) -> io::Result<()>
+    fn write_number_str<W>(&mut self, writer: &mut W, value: &str) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_number_str(writer, value))
     }
 
-    fn begin_string<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn begin_string<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.begin_string(writer))
     }
 
-    fn end_string<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn end_string<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.end_string(writer))
     }
 
-    fn write_string_fragment<W: ?Sized>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    fn write_string_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_string_fragment(writer, fragment))
     }
 
-    fn write_char_escape<W: ?Sized>(
-        &mut self,
-        writer: &mut W,
-        char_escape: CharEscape,
-    ) -> io::Result<()>
+    fn write_char_escape<W>(&mut self, writer: &mut W, char_escape: CharEscape) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_char_escape(writer, char_escape))
     }
 
-    fn begin_array<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.begin_array(writer))?;
         self.begin(writer)
     }
 
-    fn end_array<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.end();
         self.delegate(|f| f.end_array(writer))
     }
 
-    fn begin_array_value<W: ?Sized>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
-        self.delegate(|f| f.begin_array_value(writer, first))
+        self.delegate(|f| f.begin_array_value(writer, first))?;
+        self.annotate(writer)
     }
 
-    fn end_array_value<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn end_array_value<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.end_array_value(writer))
     }
 
-    fn begin_object<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.begin_object(writer))?;
         self.begin(writer)
     }
 
-    fn end_object<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.end();
         self.delegate(|f| f.end_object(writer))
     }
 
-    fn begin_object_key<W: ?Sized>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.begin_object_key(writer, first))
     }
 
-    fn end_object_key<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn end_object_key<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.end_object_key(writer))
     }
 
-    fn begin_object_value<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.begin_object_value(writer))
     }
 
-    fn end_object_value<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
+    fn end_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.end_object_value(writer))
     }
 
-    fn write_raw_fragment<W: ?Sized>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    fn write_raw_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
     where
-        W: Write,
+        W: ?Sized + Write,
     {
         self.delegate(|f| f.write_raw_fragment(writer, fragment))
     }
 }
 
+impl Mark for Excluder<'_> {
+    fn mark<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if self.writing() {
+            write!(writer, "/* decoded */ ")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ExcludeSet {
     indices: BTreeMap<u32, u32>,
@@ -0,0 +1,347 @@
+use std::io::{self, Write};
+
+use json::ser::{CharEscape, Formatter};
+
+use super::flatten::unescape;
+
+/// A formatter which can mark values that were decoded from JSON embedded in a string.
+pub trait Mark: Formatter {
+    fn mark<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write;
+}
+
+/// Wraps a formatter, writing string values which contain a JSON object or array as nested
+/// values instead, if `enabled` is set. The inner formatter sees the same events as it
+/// would for any other value, so decoded values are counted and excluded as usual.
+pub struct Expand<F> {
+    inner: F,
+    enabled: bool,
+    // Whether an object key is being written. Keys are never expanded.
+    key: bool,
+    // The contents of the string value being written.
+    string: Option<String>,
+}
+
+impl<F> Expand<F>
+where
+    F: Mark,
+{
+    pub fn new(inner: F, enabled: bool) -> Self {
+        Expand {
+            inner,
+            enabled,
+            key: false,
+            string: None,
+        }
+    }
+
+    fn write_value<W>(&mut self, writer: &mut W, value: &json::Value) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        match value {
+            json::Value::Null => self.write_null(writer),
+            json::Value::Bool(value) => self.write_bool(writer, *value),
+            json::Value::Number(number) => self.write_number_str(writer, &number.to_string()),
+            json::Value::String(value) => {
+                self.begin_string(writer)?;
+                self.write_string_fragment(writer, value)?;
+                self.end_string(writer)
+            }
+            json::Value::Array(items) => {
+                self.begin_array(writer)?;
+                for (index, item) in items.iter().enumerate() {
+                    self.begin_array_value(writer, index == 0)?;
+                    self.write_value(writer, item)?;
+                    self.end_array_value(writer)?;
+                }
+                self.end_array(writer)
+            }
+            json::Value::Object(map) => {
+                self.begin_object(writer)?;
+                for (index, (key, value)) in map.iter().enumerate() {
+                    self.begin_object_key(writer, index == 0)?;
+                    write_str(&mut self.inner, writer, key)?;
+                    self.end_object_key(writer)?;
+                    self.begin_object_value(writer)?;
+                    self.write_value(writer, value)?;
+                    self.end_object_value(writer)?;
+                }
+                self.end_object(writer)
+            }
+        }
+    }
+}
+
+/// Parses a string as JSON, if it contains an object or array.
+fn decode(string: &str) -> Option<json::Value> {
+    if !string.trim_start().starts_with(['{', '[']) {
+        return None;
+    }
+    match json::from_str(string) {
+        Ok(value @ json::Value::Object(_)) | Ok(value @ json::Value::Array(_)) => Some(value),
+        _ => None,
+    }
+}
+
+/// Writes a string to a formatter, escaping it the same way as `json::Serializer`.
+fn write_str<F, W>(f: &mut F, writer: &mut W, value: &str) -> io::Result<()>
+where
+    F: Formatter,
+    W: ?Sized + Write,
+{
+    f.begin_string(writer)?;
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        let escape = match c {
+            '"' => CharEscape::Quote,
+            '\\' => CharEscape::ReverseSolidus,
+            '\x08' => CharEscape::Backspace,
+            '\x0c' => CharEscape::FormFeed,
+            '\n' => CharEscape::LineFeed,
+            '\r' => CharEscape::CarriageReturn,
+            '\t' => CharEscape::Tab,
+            '\x00'..='\x1f' => CharEscape::AsciiControl(c as u8),
+            _ => continue,
+        };
+        if start < index {
+            f.write_string_fragment(writer, &value[start..index])?;
+        }
+        f.write_char_escape(writer, escape)?;
+        start = index + 1;
+    }
+    if start < value.len() {
+        f.write_string_fragment(writer, &value[start..])?;
+    }
+    f.end_string(writer)
+}
+
+impl<F> Formatter for Expand<F>
+where
+    F: Mark,
+{
+    fn write_null<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_null(writer)
+    }
+
+    fn write_bool<W>(&mut self, writer: &mut W, value: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_bool(writer, value)
+    }
+
+    fn write_i8<W>(&mut self, writer: &mut W, value: i8) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_i8(writer, value)
+    }
+
+    fn write_i16<W>(&mut self, writer: &mut W, value: i16) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_i16(writer, value)
+    }
+
+    fn write_i32<W>(&mut self, writer: &mut W, value: i32) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_i32(writer, value)
+    }
+
+    fn write_i64<W>(&mut self, writer: &mut W, value: i64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_i64(writer, value)
+    }
+
+    fn write_u8<W>(&mut self, writer: &mut W, value: u8) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_u8(writer, value)
+    }
+
+    fn write_u16<W>(&mut self, writer: &mut W, value: u16) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_u16(writer, value)
+    }
+
+    fn write_u32<W>(&mut self, writer: &mut W, value: u32) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_u32(writer, value)
+    }
+
+    fn write_u64<W>(&mut self, writer: &mut W, value: u64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_u64(writer, value)
+    }
+
+    fn write_f32<W>(&mut self, writer: &mut W, value: f32) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_f32(writer, value)
+    }
+
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_f64(writer, value)
+    }
+
+    fn write_number_str<W>(&mut self, writer: &mut W, value: &str) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_number_str(writer, value)
+    }
+
+    fn begin_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if self.enabled && !self.key {
+            self.string = Some(String::new());
+            Ok(())
+        } else {
+            self.inner.begin_string(writer)
+        }
+    }
+
+    fn end_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        match self.string.take() {
+            Some(string) => match decode(&string) {
+                Some(value) => {
+                    self.inner.mark(writer)?;
+                    self.write_value(writer, &value)
+                }
+                None => write_str(&mut self.inner, writer, &string),
+            },
+            None => self.inner.end_string(writer),
+        }
+    }
+
+    fn write_string_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        match &mut self.string {
+            Some(string) => {
+                string.push_str(fragment);
+                Ok(())
+            }
+            None => self.inner.write_string_fragment(writer, fragment),
+        }
+    }
+
+    fn write_char_escape<W>(&mut self, writer: &mut W, char_escape: CharEscape) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        match &mut self.string {
+            Some(string) => {
+                string.push(unescape(char_escape));
+                Ok(())
+            }
+            None => self.inner.write_char_escape(writer, char_escape),
+        }
+    }
+
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.end_array(writer)
+    }
+
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.end_object(writer)
+    }
+
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.key = true;
+        self.inner.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.key = false;
+        self.inner.end_object_key(writer)
+    }
+
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.begin_object_value(writer)
+    }
+
+    fn end_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.end_object_value(writer)
+    }
+
+    fn write_raw_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.inner.write_raw_fragment(writer, fragment)
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::BufRead;
+
+use failure::Fallible;
+use json::de::Deserializer;
+use regex::Regex;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use super::{flatten, pointer, write_value, Opts};
+use crate::filter::Filter;
+
+/// Writes the first JSON object or array in each line of the input, such as in log lines,
+/// under the rest of the line as a dimmed header. If `anchor` is given, the value must
+/// follow a match of it. Lines without a value are written unchanged, or skipped if
+/// `skip_plain` is set.
+pub fn extract<R, W>(
+    opts: Opts,
+    anchor: Option<&Regex>,
+    skip_plain: bool,
+    ptr: &str,
+    filter: Option<&Filter>,
+    mut rdr: R,
+    mut wtr: W,
+) -> Fallible<()>
+where
+    R: BufRead,
+    W: WriteColor,
+{
+    let mut buf = Vec::new();
+    let mut number = 0;
+    loop {
+        buf.clear();
+        if rdr.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        number += 1;
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        let start = match anchor {
+            Some(anchor) => anchor.find(line).map(|m| m.end()),
+            None => Some(0),
+        };
+        let (begin, end, value) = match start.and_then(|start| find(line, start)) {
+            Some(found) => found,
+            None => {
+                if !skip_plain {
+                    writeln!(wtr, "{}", line)?;
+                }
+                continue;
+            }
+        };
+        if filter.is_some_and(|filter| !filter.matches(&value)) {
+            continue;
+        }
+
+        let path = flatten::resolve(Some(&value), ptr);
+        if let Some(proj) = value.pointer(ptr) {
+            write_context(&mut wtr, line[..begin].trim(), line[end..].trim())?;
+            write_value(opts, &path, proj, &[], &mut wtr)?;
+            writeln!(wtr)?;
+        } else {
+            log::warn!(
+                "Skipping line {}: {}",
+                number,
+                pointer::not_found(&value, ptr)
+            );
+        }
+    }
+}
+
+/// Finds the first complete JSON object or array in a line at or after `start`, returning
+/// its byte range and value.
+fn find(line: &str, start: usize) -> Option<(usize, usize, json::Value)> {
+    let mut offset = start;
+    while let Some(index) = line[offset..].find(['{', '[']) {
+        let begin = offset + index;
+        let mut stream = Deserializer::from_str(&line[begin..]).into_iter::<json::Value>();
+        if let Some(Ok(value)) = stream.next() {
+            return Some((begin, begin + stream.byte_offset(), value));
+        }
+        offset = begin + 1;
+    }
+    None
+}
+
+/// Writes the text around an extracted value, dimmed in grey.
+fn write_context<W>(wtr: &mut W, prefix: &str, suffix: &str) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    if prefix.is_empty() && suffix.is_empty() {
+        return Ok(());
+    }
+    wtr.set_color(
+        ColorSpec::new()
+            .set_fg(Some(Color::Black))
+            .set_intense(true),
+    )?;
+    match (prefix.is_empty(), suffix.is_empty()) {
+        (_, true) => write!(wtr, "{}", prefix)?,
+        (true, _) => write!(wtr, "… {}", suffix)?,
+        _ => write!(wtr, "{} … {}", prefix, suffix)?,
+    }
+    wtr.reset()?;
+    writeln!(wtr)?;
+    Ok(())
+}
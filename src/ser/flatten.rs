@@ -0,0 +1,637 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use failure::{bail, ensure, format_err, Fallible, ResultExt};
+use grep_cli::is_readable_stdin;
+use json::ser::{CharEscape, CompactFormatter, Formatter};
+use structopt::StructOpt;
+
+use super::expand::{Expand, Mark};
+use super::{pointer, wrap_json_err, write_value, Opts};
+use crate::io::{stdout, Input};
+
+// Array indices are not expected to be much larger than the number of lines in the input.
+const MAX_PADDING: usize = 1 << 20;
+
+#[derive(Debug, StructOpt)]
+pub struct Unflatten {
+    /// Input file to read from
+    #[structopt(name = "FILE", parse(from_os_str))]
+    input: Option<PathBuf>,
+}
+
+impl Unflatten {
+    pub fn run(&self, opts: Opts) -> Fallible<()> {
+        let mut input = match &self.input {
+            Some(path) => Input::file(path)?,
+            None => {
+                ensure!(is_readable_stdin(), "Stdin not readable");
+                Input::stdin()?
+            }
+        };
+
+        let value = unflatten(input.to_buffer()?.get_ref())?;
+        let mut stdout = stdout();
+        write_value(opts, &[], &value, &[], &mut stdout)?;
+        Ok(stdout.flush()?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+pub fn write<F, W>(
+    js: bool,
+    path: &[Segment],
+    indices: &[usize],
+    expand: bool,
+    writer: W,
+    f: F,
+) -> Fallible<()>
+where
+    F: FnOnce(&mut json::Serializer<W, Expand<Flattener>>) -> Fallible<()>,
+    W: Write,
+{
+    let flattener = Flattener {
+        js,
+        path: path.to_vec(),
+        indices,
+        stack: Vec::new(),
+        key: None,
+        value: Vec::new(),
+    };
+    let mut ser = json::Serializer::with_formatter(writer, Expand::new(flattener, expand));
+    f(&mut ser)
+}
+
+pub struct Flattener<'a> {
+    js: bool,
+    path: Vec<Segment>,
+    // The original indices of the elements of the top-level array, if it was filtered.
+    indices: &'a [usize],
+    stack: Vec<Frame>,
+    // The object key currently being written, if any.
+    key: Option<String>,
+    // The leaf value currently being written.
+    value: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    // The number of values written to this object.
+    length: usize,
+    // Whether the path of this value has already been written, to mark it as an array.
+    declared: bool,
+}
+
+impl Flattener<'_> {
+    fn begin(&mut self, declared: bool) {
+        self.stack.push(Frame {
+            length: 0,
+            declared,
+        });
+    }
+
+    fn end<W>(&mut self, writer: &mut W, empty: &[u8]) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        let frame = self.stack.pop().unwrap();
+        if frame.length == 0 && !frame.declared {
+            self.value.clear();
+            self.value.extend_from_slice(empty);
+            self.emit(writer)?;
+        }
+        Ok(())
+    }
+
+    fn push_index(&mut self) {
+        let top_level = self.stack.len() == 1;
+        let frame = self.stack.last_mut().unwrap();
+        let index = match self.indices.get(frame.length) {
+            Some(&index) if top_level => index,
+            _ => frame.length,
+        };
+        frame.length += 1;
+        self.path.push(Segment::Index(index));
+    }
+
+    fn scalar<W>(
+        &mut self,
+        writer: &mut W,
+        f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if let Some(key) = &mut self.key {
+            let mut buf = Vec::new();
+            f(&mut buf)?;
+            key.push_str(&String::from_utf8_lossy(&buf));
+            Ok(())
+        } else {
+            self.value.clear();
+            f(&mut self.value)?;
+            self.emit(writer)
+        }
+    }
+
+    fn emit<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if self.js {
+            write_js_path(writer, &self.path)?;
+        } else {
+            write_pointer(writer, &self.path)?;
+        }
+        writer.write_all(b" = ")?;
+        writer.write_all(&self.value)?;
+        if self.js {
+            writer.write_all(b";")?;
+        }
+        writer.write_all(b"\n")
+    }
+}
+
+impl Mark for Flattener<'_> {
+    fn mark<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        Ok(())
+    }
+}
+
+impl Formatter for Flattener<'_> {
+    fn write_null<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_null(buf))
+    }
+
+    fn write_bool<W>(&mut self, writer: &mut W, value: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_bool(buf, value))
+    }
+
+    fn write_i8<W>(&mut self, writer: &mut W, value: i8) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_i8(buf, value))
+    }
+
+    fn write_i16<W>(&mut self, writer: &mut W, value: i16) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_i16(buf, value))
+    }
+
+    fn write_i32<W>(&mut self, writer: &mut W, value: i32) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_i32(buf, value))
+    }
+
+    fn write_i64<W>(&mut self, writer: &mut W, value: i64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_i64(buf, value))
+    }
+
+    fn write_u8<W>(&mut self, writer: &mut W, value: u8) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_u8(buf, value))
+    }
+
+    fn write_u16<W>(&mut self, writer: &mut W, value: u16) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_u16(buf, value))
+    }
+
+    fn write_u32<W>(&mut self, writer: &mut W, value: u32) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_u32(buf, value))
+    }
+
+    fn write_u64<W>(&mut self, writer: &mut W, value: u64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_u64(buf, value))
+    }
+
+    fn write_f32<W>(&mut self, writer: &mut W, value: f32) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_f32(buf, value))
+    }
+
+    fn write_f64<W>(&mut self, writer: &mut W, value: f64) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_f64(buf, value))
+    }
+
+    fn write_number_str<W>(&mut self, writer: &mut W, value: &str) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.scalar(writer, |buf| CompactFormatter.write_number_str(buf, value))
+    }
+
+    fn begin_string<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if self.key.is_none() {
+            self.value.clear();
+            CompactFormatter.begin_string(&mut self.value)?;
+        }
+        Ok(())
+    }
+
+    fn end_string<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        if self.key.is_none() {
+            CompactFormatter.end_string(&mut self.value)?;
+            self.emit(writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_string_fragment<W>(&mut self, _: &mut W, fragment: &str) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        match &mut self.key {
+            Some(key) => key.push_str(fragment),
+            None => CompactFormatter.write_string_fragment(&mut self.value, fragment)?,
+        }
+        Ok(())
+    }
+
+    fn write_char_escape<W>(&mut self, _: &mut W, char_escape: CharEscape) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        match &mut self.key {
+            Some(key) => key.push(unescape(char_escape)),
+            None => CompactFormatter.write_char_escape(&mut self.value, char_escape)?,
+        }
+        Ok(())
+    }
+
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        // Pointers do not distinguish array indices from object keys, so arrays are
+        // written as empty before their elements.
+        if !self.js {
+            self.value.clear();
+            self.value.extend_from_slice(b"[]");
+            self.emit(writer)?;
+        }
+        self.begin(!self.js);
+        Ok(())
+    }
+
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.end(writer, b"[]")
+    }
+
+    fn begin_array_value<W>(&mut self, _: &mut W, _: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.push_index();
+        Ok(())
+    }
+
+    fn end_array_value<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.path.pop();
+        Ok(())
+    }
+
+    fn begin_object<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.begin(false);
+        Ok(())
+    }
+
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.end(writer, b"{}")
+    }
+
+    fn begin_object_key<W>(&mut self, _: &mut W, _: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.stack.last_mut().unwrap().length += 1;
+        self.key = Some(String::new());
+        Ok(())
+    }
+
+    fn end_object_key<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        let key = self.key.take().unwrap();
+        self.path.push(Segment::Key(key));
+        Ok(())
+    }
+
+    fn begin_object_value<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        Ok(())
+    }
+
+    fn end_object_value<W>(&mut self, _: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.path.pop();
+        Ok(())
+    }
+}
+
+pub fn unescape(char_escape: CharEscape) -> char {
+    match char_escape {
+        CharEscape::Quote => '"',
+        CharEscape::ReverseSolidus => '\\',
+        CharEscape::Solidus => '/',
+        CharEscape::Backspace => '\x08',
+        CharEscape::FormFeed => '\x0c',
+        CharEscape::LineFeed => '\n',
+        CharEscape::CarriageReturn => '\r',
+        CharEscape::Tab => '\t',
+        CharEscape::AsciiControl(byte) => byte as char,
+    }
+}
+
+fn write_pointer<W>(writer: &mut W, path: &[Segment]) -> io::Result<()>
+where
+    W: ?Sized + Write,
+{
+    for segment in path {
+        match segment {
+            Segment::Key(key) => write!(writer, "/{}", escape_controls(&pointer::escape(key)))?,
+            Segment::Index(index) => write!(writer, "/{}", index)?,
+        }
+    }
+    Ok(())
+}
+
+/// Escapes backslashes and control characters in a pointer, so that it fits on one line.
+fn escape_controls(ptr: &str) -> String {
+    let mut escaped = String::with_capacity(ptr.len());
+    for ch in ptr.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn unescape_controls(ptr: &str) -> Fallible<String> {
+    let mut unescaped = String::with_capacity(ptr.len());
+    let mut chars = ptr.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let ch = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 4)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format_err!("Invalid escape `\\u{}`", hex))?;
+                unescaped.push(ch);
+            }
+            _ => bail!("Invalid escape in JSON pointer `{}`", ptr),
+        }
+    }
+    Ok(unescaped)
+}
+
+fn write_js_path<W>(writer: &mut W, path: &[Segment]) -> io::Result<()>
+where
+    W: ?Sized + Write,
+{
+    writer.write_all(b"json")?;
+    for segment in path {
+        match segment {
+            Segment::Key(key) if is_identifier(key) => write!(writer, ".{}", key)?,
+            Segment::Key(key) => {
+                writer.write_all(b"[")?;
+                json::to_writer(&mut *writer, key)?;
+                writer.write_all(b"]")?;
+            }
+            Segment::Index(index) => write!(writer, "[{}]", index)?,
+        }
+    }
+    Ok(())
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' || ch == '$' => {
+            chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '$')
+        }
+        _ => false,
+    }
+}
+
+/// Resolves the segments of a JSON pointer, using `value` to distinguish array indices
+/// from object keys.
+pub fn resolve(mut value: Option<&json::Value>, ptr: &str) -> Vec<Segment> {
+    let mut path = Vec::new();
+    for token in pointer::tokens(ptr) {
+        value = match value {
+            Some(json::Value::Array(items)) => match token.parse() {
+                Ok(index) => {
+                    path.push(Segment::Index(index));
+                    items.get(index)
+                }
+                Err(_) => {
+                    path.push(Segment::Key(token));
+                    None
+                }
+            },
+            Some(json::Value::Object(map)) => {
+                let next = map.get(&token);
+                path.push(Segment::Key(token));
+                next
+            }
+            _ => {
+                path.push(Segment::Key(token));
+                None
+            }
+        };
+    }
+    path
+}
+
+pub fn unflatten(input: &str) -> Fallible<json::Value> {
+    let mut root = json::Value::Null;
+    for (idx, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (path, value) = parse_line(&root, line).context(format!("Invalid line {}", idx + 1))?;
+        insert(&mut root, &path, value).context(format!("Invalid line {}", idx + 1))?;
+    }
+    Ok(root)
+}
+
+fn parse_line(root: &json::Value, line: &str) -> Fallible<(Vec<Segment>, json::Value)> {
+    if line.starts_with("json") {
+        parse_js_line(line)
+    } else {
+        // Keys may contain ` = `, so find the first split which leaves a valid value.
+        for (idx, _) in line.match_indices(" = ") {
+            if let Ok(value) = json::from_str(&line[idx + 3..]) {
+                return Ok((parse_pointer(root, &line[..idx])?, value));
+            }
+        }
+        bail!("Expected a line of the form `<pointer> = <value>`")
+    }
+}
+
+/// Parses a pointer, treating a token as an array index only where the value it leads
+/// to has already been set to an array.
+fn parse_pointer(root: &json::Value, ptr: &str) -> Fallible<Vec<Segment>> {
+    ensure!(
+        ptr.is_empty() || ptr.starts_with('/'),
+        "Invalid JSON pointer `{}`",
+        ptr
+    );
+    Ok(resolve(Some(root), &unescape_controls(ptr)?))
+}
+
+fn parse_js_line(line: &str) -> Fallible<(Vec<Segment>, json::Value)> {
+    let mut path = Vec::new();
+    let mut rest = &line["json".len()..];
+    loop {
+        if let Some(tail) = rest.strip_prefix('.') {
+            let end = tail
+                .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '$'))
+                .unwrap_or(tail.len());
+            path.push(Segment::Key(tail[..end].to_owned()));
+            rest = &tail[end..];
+        } else if let Some(tail) = rest.strip_prefix('[') {
+            if tail.starts_with('"') {
+                let mut stream = json::Deserializer::from_str(tail).into_iter::<String>();
+                let key = match stream.next() {
+                    Some(key) => key.map_err(wrap_json_err)?,
+                    None => bail!("Expected a string key"),
+                };
+                path.push(Segment::Key(key));
+                rest = &tail[stream.byte_offset()..];
+            } else {
+                let end = tail.find(']').unwrap_or(tail.len());
+                let index = tail[..end]
+                    .parse::<usize>()
+                    .context(format!("Invalid array index `{}`", &tail[..end]))?;
+                path.push(Segment::Index(index));
+                rest = &tail[end..];
+            }
+            rest = rest
+                .strip_prefix(']')
+                .ok_or_else(|| format_err!("Expected `]`"))?;
+        } else if let Some(value) = rest.strip_prefix(" = ") {
+            let value = value.trim_end();
+            let value = value.strip_suffix(';').unwrap_or(value);
+            return Ok((path, json::from_str(value).map_err(wrap_json_err)?));
+        } else {
+            bail!("Expected a line of the form `json.path = <value>;`")
+        }
+    }
+}
+
+fn insert(root: &mut json::Value, path: &[Segment], value: json::Value) -> Fallible<()> {
+    let mut target = root;
+    for segment in path {
+        if target.is_null() {
+            *target = match segment {
+                Segment::Key(_) => json::Value::Object(json::Map::new()),
+                Segment::Index(_) => json::Value::Array(Vec::new()),
+            };
+        }
+
+        target = match (target, segment) {
+            (json::Value::Object(map), Segment::Key(key)) => {
+                map.entry(key.as_str()).or_insert(json::Value::Null)
+            }
+            (json::Value::Object(map), Segment::Index(index)) => {
+                map.entry(index.to_string()).or_insert(json::Value::Null)
+            }
+            (json::Value::Array(items), Segment::Index(index)) => {
+                ensure!(
+                    *index <= items.len() + MAX_PADDING,
+                    "Array index {} is too large",
+                    index
+                );
+                if items.len() <= *index {
+                    items.resize(index + 1, json::Value::Null);
+                }
+                &mut items[*index]
+            }
+            (json::Value::Array(_), Segment::Key(key)) => {
+                bail!("Cannot index array with key `{}`", key)
+            }
+            (_, _) => bail!("Cannot set a value inside a scalar"),
+        };
+    }
+
+    *target = value;
+    Ok(())
+}
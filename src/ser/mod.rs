@@ -1,17 +1,44 @@
+mod convert;
 mod count;
+mod document;
 mod exclude;
+mod expand;
+mod extract;
+mod flatten;
+mod pointer;
+mod relaxed;
+mod repair;
 #[cfg(test)]
 mod tests;
+mod track;
+mod transcode;
 
-use std::io::{Read, Seek, SeekFrom, Write};
+pub use self::convert::Foreign;
+pub use self::extract::extract;
+pub use self::flatten::Unflatten;
+pub use self::relaxed::relax;
+pub use self::repair::repair;
+pub use self::track::ParseError;
+pub use self::transcode::Encoding;
 
-use failure::{Error, Fallible};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use failure::{bail, ensure, Error, Fallible};
 use json::de::Deserializer;
+use serde::de::IgnoredAny;
 use serde::ser::{Serialize, Serializer};
-use serde_transcode::transcode;
 use structopt::StructOpt;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use self::convert::Visit;
+use self::document::{documents, slice_documents};
+use self::exclude::ExcludeSet;
+use self::flatten::Segment;
+use self::track::Tracker;
+use crate::filter::Filter;
 
-#[derive(Copy, Clone, Debug, StructOpt)]
+#[derive(Copy, Clone, Debug, Default, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 pub struct Opts {
     /// The maximum number of lines a JSON value can take up when printed.
@@ -20,6 +47,25 @@ pub struct Opts {
     /// The maximum depth to which a JSON value should be printed.
     #[structopt(long, short = "D")]
     max_depth: Option<u32>,
+    /// Print each leaf value on its own line, prefixed by its path. Without --js, each
+    /// array is first written as `[]` to tell its indices apart from object keys.
+    #[structopt(long, conflicts_with = "max_length", conflicts_with = "max_depth")]
+    flatten: bool,
+    /// Use JavaScript syntax for paths with the --flatten option.
+    #[structopt(long, requires = "flatten")]
+    js: bool,
+    /// Wrap all documents in the input in a single array, instead of writing each one
+    /// separately.
+    #[structopt(long)]
+    slurp: bool,
+    /// Render strings containing a JSON object or array as nested values, marked as
+    /// decoded.
+    #[structopt(long)]
+    expand_strings: bool,
+    /// Sort the keys of objects, instead of keeping them in the order of the input. Each
+    /// document is read into memory to sort it, rather than streamed.
+    #[structopt(long)]
+    sort_keys: bool,
 }
 
 fn non_zero(arg: String) -> Result<(), String> {
@@ -31,64 +77,471 @@ fn non_zero(arg: String) -> Result<(), String> {
 }
 
 impl Opts {
+    pub fn slurp(&self) -> bool {
+        self.slurp
+    }
+
     fn is_identity(&self) -> bool {
-        self.max_length.is_none() && self.max_depth.is_none()
+        self.max_length.is_none()
+            && self.max_depth.is_none()
+            && !self.expand_strings
+            && !self.sort_keys
     }
 }
 
-pub fn project<R, W>(opts: Opts, ptr: &str, rdr: R, wtr: W) -> Fallible<()>
+pub fn project<R, W>(
+    opts: Opts,
+    ptr: &str,
+    filter: Option<&Filter>,
+    rdr: R,
+    mut wtr: W,
+) -> Fallible<()>
 where
     R: Read,
     W: Write,
 {
-    let value: json::Value = json::from_reader(rdr).map_err(wrap_json_err)?;
+    documents(opts.slurp, rdr, |index, doc| {
+        project_document(opts, index, doc.value()?, ptr, filter, &mut wtr)
+    })
+}
+
+fn project_document<W>(
+    opts: Opts,
+    index: usize,
+    mut value: json::Value,
+    ptr: &str,
+    filter: Option<&Filter>,
+    mut wtr: W,
+) -> Fallible<()>
+where
+    W: Write,
+{
+    if index > 0 {
+        writeln!(wtr)?;
+    }
+
+    let path = flatten::resolve(Some(&value), ptr);
+    match value.pointer_mut(ptr) {
+        Some(proj) => write_projection(opts, &path, proj, filter, &mut wtr),
+        None => Err(pointer::not_found(&value, ptr)),
+    }
+}
+
+/// Like `project`, for `value`, the subtree of a document at the pointer `base`. `arrays`
+/// holds, for each token of `base`, whether it indexes an array. Returns `false` without
+/// writing anything if `ptr` is not found in `value`, so the whole document can be read
+/// to explain why.
+pub fn project_subtree<W>(
+    opts: Opts,
+    base: &str,
+    arrays: &[bool],
+    mut value: json::Value,
+    ptr: &str,
+    filter: Option<&Filter>,
+    mut wtr: W,
+) -> Fallible<bool>
+where
+    W: Write,
+{
+    let mut path: Vec<_> = pointer::tokens(base)
+        .zip(arrays)
+        .map(|(token, &array)| match token.parse() {
+            Ok(index) if array => Segment::Index(index),
+            _ => Segment::Key(token),
+        })
+        .collect();
+    path.extend(flatten::resolve(Some(&value), ptr));
+    match value.pointer_mut(ptr) {
+        Some(proj) => write_projection(opts, &path, proj, filter, &mut wtr).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+fn write_projection<W>(
+    opts: Opts,
+    path: &[Segment],
+    proj: &mut json::Value,
+    filter: Option<&Filter>,
+    wtr: W,
+) -> Fallible<()>
+where
+    W: Write,
+{
+    if let Some(filter) = filter {
+        let items = match proj.take() {
+            json::Value::Array(items) => items,
+            _ => bail!("Cannot filter a value which is not an array"),
+        };
+        let (indices, items): (Vec<_>, Vec<_>) = items
+            .into_iter()
+            .enumerate()
+            .filter(|(_, item)| filter.matches(item))
+            .unzip();
+        write_value(opts, path, &json::Value::Array(items), &indices, wtr)
+    } else {
+        write_value(opts, path, proj, &[], wtr)
+    }
+}
+
+/// Writes each value in a stream of JSON values, such as newline-delimited JSON, under a
+/// header with its record number. Records not matching `filter` are skipped.
+pub fn lines<R, W>(opts: Opts, ptr: &str, filter: Option<&Filter>, rdr: R, wtr: W) -> Fallible<()>
+where
+    R: Read,
+    W: WriteColor,
+{
+    track::writes(wtr, |mut wtr| {
+        let mut rdr = Tracker::new(rdr);
+        let mut stream = Deserializer::from_reader(&mut rdr).into_iter::<json::Value>();
+        let mut number = 0;
+        let mut misses = Misses::default();
+        let err = loop {
+            let value = match stream.next() {
+                Some(Ok(value)) => value,
+                Some(Err(err)) => break err,
+                None => return misses.check(ptr),
+            };
+            number += 1;
+            misses.add(write_record(opts, number, ptr, filter, &value, &mut wtr)?);
+        };
+        Err(rdr.error(err))
+    })
+}
+
+/// Writes a single record under a header with its number, unless it does not match
+/// `filter`. Returns false if the record has no value at `ptr`.
+pub fn write_record<N, W>(
+    opts: Opts,
+    number: N,
+    ptr: &str,
+    filter: Option<&Filter>,
+    value: &json::Value,
+    mut wtr: W,
+) -> Fallible<bool>
+where
+    N: fmt::Display,
+    W: WriteColor,
+{
+    if filter.is_some_and(|filter| !filter.matches(value)) {
+        return Ok(true);
+    }
+
+    let path = flatten::resolve(Some(value), ptr);
     if let Some(proj) = value.pointer(ptr) {
-        if opts.is_identity() {
-            Ok(json::to_writer_pretty(wtr, proj).map_err(wrap_json_err)?)
+        write_header(&mut wtr, &format!("record {}", number))?;
+        write_value(opts, &path, proj, &[], &mut wtr)?;
+        writeln!(wtr)?;
+        Ok(true)
+    } else {
+        log::warn!(
+            "Skipping record {}: {}",
+            number,
+            pointer::not_found(value, ptr)
+        );
+        Ok(false)
+    }
+}
+
+/// Counts the records without a value at a pointer, to fail if no record has one.
+#[derive(Debug, Default)]
+pub struct Misses {
+    found: bool,
+    missed: usize,
+}
+
+impl Misses {
+    pub fn add(&mut self, found: bool) {
+        if found {
+            self.found = true;
         } else {
-            let excludes = count::count(opts, |ser| proj.serialize(ser).map_err(wrap_json_err))?;
-            exclude::write(excludes, wtr, |ser| {
-                proj.serialize(ser).map_err(wrap_json_err)
-            })
+            self.missed += 1;
         }
-    } else {
-        log::warn!("No value found for JSON pointer `{}`.", ptr);
+    }
+
+    pub fn check(&self, ptr: &str) -> Fallible<()> {
+        ensure!(
+            self.found || self.missed == 0,
+            "No value found for JSON pointer `{}` in any of the {} records",
+            ptr,
+            self.missed
+        );
         Ok(())
     }
 }
 
+/// Guesses whether the input is newline-delimited JSON, from whether its first line is a
+/// complete value followed by more input.
+pub fn is_lines(buf: &[u8]) -> bool {
+    match buf.iter().position(|&byte| byte == b'\n') {
+        Some(end) => {
+            let (first, rest) = buf.split_at(end);
+            !first.iter().all(u8::is_ascii_whitespace)
+                && !rest.iter().all(u8::is_ascii_whitespace)
+                && json::from_slice::<IgnoredAny>(first).is_ok()
+        }
+        None => false,
+    }
+}
+
+pub fn write_header<W>(wtr: &mut W, header: &str) -> io::Result<()>
+where
+    W: WriteColor,
+{
+    wtr.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)).set_bold(true))?;
+    write!(wtr, "// {}", header)?;
+    wtr.reset()?;
+    writeln!(wtr)
+}
+
+fn write_value<W>(
+    opts: Opts,
+    path: &[Segment],
+    value: &json::Value,
+    indices: &[usize],
+    wtr: W,
+) -> Fallible<()>
+where
+    W: Write,
+{
+    let sorted;
+    let value = if opts.sort_keys {
+        sorted = sort_keys(value.clone());
+        &sorted
+    } else {
+        value
+    };
+
+    if opts.flatten {
+        flatten::write(opts.js, path, indices, opts.expand_strings, wtr, |ser| {
+            value.serialize(ser).map_err(wrap_json_err)
+        })
+    } else if opts.is_identity() && indices.is_empty() {
+        Ok(json::to_writer_pretty(wtr, value).map_err(wrap_json_err)?)
+    } else {
+        let excludes = count::count(opts, |ser| value.serialize(ser).map_err(wrap_json_err))?;
+        exclude::write(excludes, indices, opts.expand_strings, wtr, |ser| {
+            value.serialize(ser).map_err(wrap_json_err)
+        })
+    }
+}
+
+/// Sorts the keys of every object in a value.
+fn sort_keys(value: json::Value) -> json::Value {
+    match value {
+        json::Value::Array(items) => json::Value::Array(items.into_iter().map(sort_keys).collect()),
+        json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        value => value,
+    }
+}
+
+/// Reads the input into a single value, wrapping it in an array if it contains several
+/// documents.
+pub fn read_value<R>(rdr: R) -> Fallible<json::Value>
+where
+    R: Read,
+{
+    let mut values = Vec::new();
+    documents(false, rdr, |_, doc| {
+        values.push(doc.value()?);
+        Ok(())
+    })?;
+    Ok(if values.len() == 1 {
+        values.pop().unwrap()
+    } else {
+        json::Value::Array(values)
+    })
+}
+
 pub fn shorten<R, W>(opts: Opts, mut rdr: R, wtr: W) -> Fallible<()>
 where
     R: Read + Seek,
     W: Write,
 {
-    if opts.is_identity() {
-        identity(rdr, wtr)
+    if opts.sort_keys {
+        return track::writes(wtr, |wtr| project(opts, "", None, rdr, wtr));
+    }
+
+    track::writes(wtr, |mut wtr| {
+        let mut excludes = Vec::new();
+        if !opts.flatten && !opts.is_identity() {
+            documents(opts.slurp, rdr.by_ref(), |_, doc| {
+                excludes.push(count::count(opts, |ser| serialize(doc, ser))?);
+                Ok(())
+            })?;
+            rdr.seek(SeekFrom::Start(0))?;
+        }
+
+        let mut excludes = excludes.into_iter();
+        documents(opts.slurp, rdr, |index, doc| {
+            write_document(opts, index, excludes.next(), doc, &mut wtr)
+        })
+    })
+}
+
+fn write_document<D, W>(
+    opts: Opts,
+    index: usize,
+    excludes: Option<ExcludeSet>,
+    doc: D,
+    mut wtr: W,
+) -> Fallible<()>
+where
+    D: Serialize,
+    W: Write,
+{
+    if index > 0 {
+        writeln!(wtr)?;
+    }
+
+    if opts.flatten {
+        flatten::write(opts.js, &[], &[], opts.expand_strings, &mut wtr, |ser| {
+            serialize(doc, ser)
+        })
+    } else if let Some(excludes) = excludes {
+        exclude::write(excludes, &[], opts.expand_strings, &mut wtr, |ser| {
+            serialize(doc, ser)
+        })
+    } else {
+        serialize(doc, &mut json::Serializer::pretty(&mut wtr))
+    }
+}
+
+/// Like `shorten`, but parses the input directly from a byte slice, such as a memory-mapped
+/// file, on both passes.
+pub fn shorten_slice<W>(opts: Opts, data: &[u8], wtr: W) -> Fallible<()>
+where
+    W: Write,
+{
+    if opts.sort_keys {
+        return track::writes(wtr, |wtr| project(opts, "", None, data, wtr));
+    }
+
+    track::writes(wtr, |mut wtr| {
+        let mut excludes = Vec::new();
+        if !opts.flatten && !opts.is_identity() {
+            slice_documents(opts.slurp, data, |_, doc| {
+                excludes.push(count::count(opts, |ser| serialize(doc, ser))?);
+                Ok(())
+            })?;
+        }
+
+        let mut excludes = excludes.into_iter();
+        slice_documents(opts.slurp, data, |index, doc| {
+            write_document(opts, index, excludes.next(), doc, &mut wtr)
+        })
+    })
+}
+
+/// Writes input in a format other than JSON, like `shorten`, `project` or `lines` do for
+/// JSON. When shortening, each document is transcoded straight into the formatters on
+/// both passes, and otherwise it is read into a value.
+pub fn convert<W>(
+    opts: Opts,
+    foreign: Foreign,
+    data: &[u8],
+    ptr: &str,
+    filter: Option<&Filter>,
+    lines: bool,
+    mut wtr: W,
+) -> Fallible<()>
+where
+    W: WriteColor,
+{
+    if ptr.is_empty() && filter.is_none() && !lines && !opts.slurp && !opts.sort_keys {
+        return shorten_foreign(opts, foreign, data, wtr);
+    }
+
+    let mut values = foreign.values(data)?;
+    if opts.slurp {
+        values = vec![json::Value::Array(values)];
+    }
+    if lines {
+        let mut misses = Misses::default();
+        for (index, value) in values.iter().enumerate() {
+            misses.add(write_record(opts, index + 1, ptr, filter, value, &mut wtr)?);
+        }
+        misses.check(ptr)
     } else {
-        let excludes = count::count(opts, |ser| serialize(rdr.by_ref(), ser))?;
-        rdr.seek(SeekFrom::Start(0))?;
-        exclude::write(excludes, wtr, |ser| serialize(rdr, ser))
+        for (index, value) in values.into_iter().enumerate() {
+            project_document(opts, index, value, ptr, filter, &mut wtr)?;
+        }
+        Ok(())
     }
 }
 
+fn shorten_foreign<W>(opts: Opts, foreign: Foreign, data: &[u8], wtr: W) -> Fallible<()>
+where
+    W: Write,
+{
+    struct Count(Opts, Vec<ExcludeSet>);
+
+    impl Visit for Count {
+        fn document<T>(&mut self, _: usize, doc: &T) -> Fallible<()>
+        where
+            T: Serialize,
+        {
+            let excludes = count::count(self.0, |ser| serialize(doc, ser))?;
+            self.1.push(excludes);
+            Ok(())
+        }
+    }
+
+    struct Writer<I, W>(Opts, I, W);
+
+    impl<I, W> Visit for Writer<I, W>
+    where
+        I: Iterator<Item = ExcludeSet>,
+        W: Write,
+    {
+        fn document<T>(&mut self, index: usize, doc: &T) -> Fallible<()>
+        where
+            T: Serialize,
+        {
+            write_document(self.0, index, self.1.next(), doc, &mut self.2)
+        }
+    }
+
+    track::writes(wtr, |wtr| {
+        let mut count = Count(opts, Vec::new());
+        if !opts.flatten && !opts.is_identity() {
+            foreign.documents(data, &mut count)?;
+        }
+        foreign.documents(data, &mut Writer(opts, count.1.into_iter(), wtr))
+    })
+}
+
 pub fn identity<R, W>(rdr: R, wtr: W) -> Fallible<()>
 where
     R: Read,
     W: Write,
 {
-    serialize(rdr, &mut json::Serializer::pretty(wtr))
+    track::writes(wtr, |mut wtr| {
+        documents(false, rdr, |index, doc| {
+            if index > 0 {
+                writeln!(wtr)?;
+            }
+            serialize(doc, &mut json::Serializer::pretty(&mut wtr))
+        })
+    })
 }
 
-fn serialize<R, S>(rdr: R, ser: S) -> Fallible<()>
+fn serialize<D, S>(doc: D, ser: S) -> Fallible<()>
 where
-    R: Read,
+    D: Serialize,
     S: Serializer<Ok = (), Error = json::Error>,
 {
-    let mut de = Deserializer::from_reader(rdr);
-    transcode(&mut de, ser).map_err(wrap_json_err)
+    doc.serialize(ser).map_err(wrap_json_err)
 }
 
 fn wrap_json_err(e: json::Error) -> Error {
-    // TODO: preserve error through transcode and add context
     e.into()
 }
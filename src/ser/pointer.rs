@@ -0,0 +1,203 @@
+use std::fmt::Write;
+
+use failure::{format_err, Error};
+
+const MAX_KEYS: usize = 10;
+const MAX_SUGGESTIONS: usize = 3;
+
+pub fn tokens(ptr: &str) -> impl Iterator<Item = String> + '_ {
+    ptr.split('/')
+        .skip(1)
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+}
+
+pub fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Explains why `ptr` could not be resolved in `value`.
+pub fn not_found(value: &json::Value, ptr: &str) -> Error {
+    let tokens: Vec<_> = tokens(ptr).collect();
+    let mut message = format!("No value found for JSON pointer `{}`", ptr);
+    if !ptr.is_empty() && !ptr.starts_with('/') {
+        write!(message, "\npointer must start with `/`").unwrap();
+        return format_err!("{}", message);
+    }
+
+    let mut prefix = String::new();
+    let mut current = value;
+    for (idx, token) in tokens.iter().enumerate() {
+        let rest = &tokens[idx + 1..];
+        let next = match current {
+            json::Value::Object(map) => map.get(token),
+            json::Value::Array(items) => index(token).and_then(|idx| items.get(idx)),
+            _ => None,
+        };
+        if let Some(next) = next {
+            current = next;
+            write!(prefix, "/{}", escape(token)).unwrap();
+            continue;
+        }
+
+        let location = describe(&prefix, current);
+        let suggestions = match current {
+            json::Value::Object(map) => {
+                write!(message, "\n{} has no key `{}`", location, token).unwrap();
+                if !map.is_empty() {
+                    let keys: Vec<_> = map
+                        .keys()
+                        .take(MAX_KEYS)
+                        .map(|key| format!("`{}`", key))
+                        .collect();
+                    write!(message, ", available keys are {}", keys.join(", ")).unwrap();
+                    if map.len() > MAX_KEYS {
+                        write!(message, " and {} more", map.len() - MAX_KEYS).unwrap();
+                    }
+                }
+                suggest_keys(map, &prefix, token, rest)
+            }
+            json::Value::Array(items) => {
+                match index(token) {
+                    Some(index) => write!(
+                        message,
+                        "\nindex {} is out of range for {}, which has length {}",
+                        index,
+                        location,
+                        items.len()
+                    ),
+                    None => write!(
+                        message,
+                        "\n`{}` is not a valid index for {}",
+                        token, location
+                    ),
+                }
+                .unwrap();
+                suggest_index(items, &prefix, token, rest)
+            }
+            value => {
+                write!(
+                    message,
+                    "\n{} is {} and has no children",
+                    location,
+                    kind(value)
+                )
+                .unwrap();
+                Vec::new()
+            }
+        };
+
+        match suggestions.as_slice() {
+            [] => (),
+            [suggestion] => write!(message, "\ndid you mean `{}`?", suggestion).unwrap(),
+            suggestions => {
+                let suggestions: Vec<_> =
+                    suggestions.iter().map(|ptr| format!("`{}`", ptr)).collect();
+                write!(message, "\ndid you mean one of {}?", suggestions.join(", ")).unwrap()
+            }
+        }
+        break;
+    }
+
+    format_err!("{}", message)
+}
+
+fn suggest_keys(
+    map: &json::Map<String, json::Value>,
+    prefix: &str,
+    token: &str,
+    rest: &[String],
+) -> Vec<String> {
+    let mut candidates: Vec<_> = map
+        .iter()
+        .map(|(key, value)| (similarity(token, key), key, value))
+        .filter(|&(score, _, _)| score > 0.8)
+        .collect();
+    // Prefer keys which allow the rest of the pointer to be resolved.
+    candidates.sort_by(|lhs, rhs| {
+        let lhs_resolves = resolve(lhs.2, rest).is_some();
+        let rhs_resolves = resolve(rhs.2, rest).is_some();
+        rhs_resolves
+            .cmp(&lhs_resolves)
+            .then(rhs.0.partial_cmp(&lhs.0).unwrap())
+    });
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, key, _)| join(prefix, key, rest))
+        .collect()
+}
+
+fn suggest_index(items: &[json::Value], prefix: &str, token: &str, rest: &[String]) -> Vec<String> {
+    // The pointer may be missing an array index, for example `/users/email` instead of
+    // `/users/0/email`.
+    let tokens: Vec<_> = Some(token.to_owned())
+        .into_iter()
+        .chain(rest.iter().cloned())
+        .collect();
+    items
+        .iter()
+        .position(|item| resolve(item, &tokens).is_some())
+        .map(|index| join(prefix, &index.to_string(), &tokens))
+        .into_iter()
+        .collect()
+}
+
+fn resolve<'a>(mut value: &'a json::Value, tokens: &[String]) -> Option<&'a json::Value> {
+    for token in tokens {
+        value = match value {
+            json::Value::Object(map) => map.get(token)?,
+            json::Value::Array(items) => items.get(index(token)?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Parses an array index the way `json` does, without leading zeros or a sign.
+fn index(token: &str) -> Option<usize> {
+    if token.starts_with('+') || (token.starts_with('0') && token.len() > 1) {
+        None
+    } else {
+        token.parse().ok()
+    }
+}
+
+fn join(prefix: &str, token: &str, rest: &[String]) -> String {
+    let mut ptr = format!("{}/{}", prefix, escape(token));
+    for token in rest {
+        write!(ptr, "/{}", escape(token)).unwrap();
+    }
+    ptr
+}
+
+fn similarity(lhs: &str, rhs: &str) -> f64 {
+    if lhs.eq_ignore_ascii_case(rhs) {
+        1.0
+    } else {
+        strsim::jaro_winkler(lhs, rhs)
+    }
+}
+
+fn describe(prefix: &str, value: &json::Value) -> String {
+    let noun = match value {
+        json::Value::Object(_) => "object",
+        json::Value::Array(_) => "array",
+        _ => "value",
+    };
+    if prefix.is_empty() {
+        format!("the root {}", noun)
+    } else {
+        format!("the {} at `{}`", noun, prefix)
+    }
+}
+
+fn kind(value: &json::Value) -> &'static str {
+    match value {
+        json::Value::Null => "null",
+        json::Value::Bool(_) => "a boolean",
+        json::Value::Number(_) => "a number",
+        json::Value::String(_) => "a string",
+        json::Value::Array(_) => "an array",
+        json::Value::Object(_) => "an object",
+    }
+}
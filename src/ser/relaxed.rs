@@ -0,0 +1,226 @@
+use std::io::Cursor;
+
+/// Converts JSON with comments and trailing commas to strict JSON. If `json5` is set, the
+/// rest of the JSON5 syntax is also accepted, such as unquoted keys, single-quoted strings
+/// and hexadecimal numbers.
+///
+/// Comments and trailing commas are replaced with whitespace, so positions in the JSON
+/// with comments are unchanged.
+pub fn relax(buf: &mut Cursor<String>, json5: bool) {
+    let relaxed = {
+        let input = buf.get_ref().as_bytes();
+        Relaxer {
+            input,
+            pos: 0,
+            out: Vec::with_capacity(input.len()),
+            comma: None,
+            json5,
+        }
+        .run()
+    };
+    *buf = Cursor::new(relaxed);
+}
+
+struct Relaxer<'a> {
+    input: &'a [u8],
+    pos: usize,
+    out: Vec<u8>,
+    // The position in the output of the last comma, if it may be a trailing comma.
+    comma: Option<usize>,
+    json5: bool,
+}
+
+impl Relaxer<'_> {
+    fn run(mut self) -> String {
+        while let Some(byte) = self.peek(0) {
+            match byte {
+                b'/' if self.peek(1) == Some(b'/') => self.line_comment(),
+                b'/' if self.peek(1) == Some(b'*') => self.block_comment(),
+                b' ' | b'\t' | b'\n' | b'\r' => self.copy(1),
+                _ => {
+                    if let (Some(comma), b']') | (Some(comma), b'}') = (self.comma, byte) {
+                        self.out[comma] = b' ';
+                    }
+                    self.comma = None;
+
+                    match byte {
+                        b',' => {
+                            self.comma = Some(self.out.len());
+                            self.copy(1);
+                        }
+                        b'"' => self.string(b'"'),
+                        b'\'' if self.json5 => self.string(b'\''),
+                        b'0'..=b'9' | b'.' | b'+' | b'-' if self.json5 => self.number(),
+                        _ if self.json5 && is_identifier(byte) => self.identifier(),
+                        _ => self.copy(1),
+                    }
+                }
+            }
+        }
+
+        String::from_utf8(self.out).unwrap()
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.input.get(self.pos + offset).cloned()
+    }
+
+    fn copy(&mut self, len: usize) {
+        let end = (self.pos + len).min(self.input.len());
+        self.out.extend_from_slice(&self.input[self.pos..end]);
+        self.pos = end;
+    }
+
+    fn line_comment(&mut self) {
+        while let Some(byte) = self.peek(0) {
+            if byte == b'\n' {
+                break;
+            }
+            self.blank(byte);
+        }
+    }
+
+    fn block_comment(&mut self) {
+        self.blank(b'/');
+        self.blank(b'*');
+        while let Some(byte) = self.peek(0) {
+            if byte == b'*' && self.peek(1) == Some(b'/') {
+                self.blank(b'*');
+                self.blank(b'/');
+                break;
+            }
+            self.blank(byte);
+        }
+    }
+
+    /// Replaces a byte with whitespace, keeping line breaks.
+    fn blank(&mut self, byte: u8) {
+        self.out.push(if byte == b'\n' || byte == b'\r' {
+            byte
+        } else {
+            b' '
+        });
+        self.pos += 1;
+    }
+
+    fn string(&mut self, quote: u8) {
+        self.out.push(b'"');
+        self.pos += 1;
+        while let Some(byte) = self.peek(0) {
+            match byte {
+                _ if byte == quote => {
+                    self.out.push(b'"');
+                    self.pos += 1;
+                    return;
+                }
+                b'"' => {
+                    self.out.extend_from_slice(b"\\\"");
+                    self.pos += 1;
+                }
+                b'\\' if self.json5 => self.escape(),
+                b'\\' => self.copy(2),
+                _ => self.copy(1),
+            }
+        }
+    }
+
+    fn escape(&mut self) {
+        match self.peek(1) {
+            Some(b'"') | Some(b'\\') | Some(b'/') | Some(b'b') | Some(b'f') | Some(b'n')
+            | Some(b'r') | Some(b't') | Some(b'u') => self.copy(2),
+            // A backslash before a line break continues the string on the next line.
+            Some(b'\r') if self.peek(2) == Some(b'\n') => self.pos += 3,
+            Some(b'\n') | Some(b'\r') => self.pos += 2,
+            Some(b'v') => self.unicode(2, "000b"),
+            Some(b'0') if !self.peek(2).is_some_and(|byte| byte.is_ascii_digit()) => {
+                self.unicode(2, "0000")
+            }
+            Some(b'x') => {
+                let hex = self.input.get(self.pos + 2..self.pos + 4).unwrap_or(b"");
+                match std::str::from_utf8(hex) {
+                    Ok(hex) if hex.bytes().all(|byte| byte.is_ascii_hexdigit()) => {
+                        self.unicode(4, &format!("00{}", hex))
+                    }
+                    _ => self.copy(2),
+                }
+            }
+            // Any other escaped character stands for itself.
+            Some(_) => {
+                self.pos += 1;
+                self.copy(1);
+            }
+            None => self.copy(1),
+        }
+    }
+
+    fn unicode(&mut self, len: usize, hex: &str) {
+        self.out.extend_from_slice(b"\\u");
+        self.out.extend_from_slice(hex.as_bytes());
+        self.pos += len;
+    }
+
+    fn number(&mut self) {
+        let start = self.pos;
+        let mut prev = 0;
+        while let Some(byte) = self.peek(0) {
+            let exponent = (byte == b'+' || byte == b'-') && (prev == b'e' || prev == b'E');
+            if !(byte.is_ascii_alphanumeric() || byte == b'.' || exponent || self.pos == start) {
+                break;
+            }
+            prev = byte;
+            self.pos += 1;
+        }
+
+        let token = String::from_utf8_lossy(&self.input[start..self.pos]).into_owned();
+        let (sign, digits) = match token.chars().next() {
+            Some('-') => ("-", &token[1..]),
+            Some('+') => ("", &token[1..]),
+            _ => ("", &token[..]),
+        };
+        let number = if digits == "Infinity" || digits == "NaN" {
+            format!("\"{}{}\"", sign, digits)
+        } else if digits.starts_with("0x") || digits.starts_with("0X") {
+            match u64::from_str_radix(&digits[2..], 16) {
+                Ok(value) => format!("{}{}", sign, value),
+                Err(_) => token.clone(),
+            }
+        } else {
+            let mut number = format!("{}{}", sign, digits);
+            if digits.starts_with('.') {
+                number.insert(sign.len(), '0');
+            }
+            if let Some(idx) = number.find('.') {
+                let fraction = number[idx + 1..].chars().next();
+                if !fraction.is_some_and(|c| c.is_ascii_digit()) {
+                    number.remove(idx);
+                }
+            }
+            number
+        };
+        self.out.extend_from_slice(number.as_bytes());
+    }
+
+    fn identifier(&mut self) {
+        let start = self.pos;
+        while self
+            .peek(0)
+            .is_some_and(|byte| is_identifier(byte) || byte.is_ascii_digit())
+        {
+            self.pos += 1;
+        }
+
+        let word = &self.input[start..self.pos];
+        match word {
+            b"true" | b"false" | b"null" => self.out.extend_from_slice(word),
+            _ => {
+                self.out.push(b'"');
+                self.out.extend_from_slice(word);
+                self.out.push(b'"');
+            }
+        }
+    }
+}
+
+fn is_identifier(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_' || byte == b'$' || byte >= 0x80
+}
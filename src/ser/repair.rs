@@ -0,0 +1,61 @@
+use std::io::Cursor;
+
+use failure::Fallible;
+use json::de::Deserializer;
+use serde::de::IgnoredAny;
+
+use super::track::{Frame, Tracker};
+
+/// Cuts invalid or truncated JSON at the last complete value and closes any open
+/// containers, marking the innermost one with the reason the input was cut.
+pub fn repair(buf: &mut Cursor<String>) -> Fallible<()> {
+    let repaired = {
+        let data = buf.get_ref();
+        let mut rdr = Tracker::new(data.as_bytes());
+        let result = Deserializer::from_reader(&mut rdr)
+            .into_iter::<IgnoredAny>()
+            .find_map(Result::err);
+        let err = match result {
+            None => return Ok(()),
+            Some(err) => rdr.parse_error(err)?,
+        };
+        log::warn!("Rendering input up to the first error: {}", err);
+
+        let reason = if err.message().starts_with("EOF") {
+            "unexpected EOF"
+        } else {
+            err.message()
+        };
+        let marker = json::Value::String(format!(
+            "<truncated here: {} at line {}>",
+            reason,
+            err.line()
+        ))
+        .to_string();
+
+        let scanner = rdr.scanner();
+        let mut repaired = data[..scanner.safe()].to_owned();
+        if !scanner.empty() && !scanner.stack().is_empty() {
+            repaired.push_str(", ");
+        }
+        match scanner.stack().last() {
+            Some(Frame::Array(_)) => repaired.push_str(&marker),
+            Some(Frame::Object(_)) => {
+                repaired.push_str(&marker);
+                repaired.push_str(": null");
+            }
+            None if scanner.safe() == 0 => repaired.push_str(&marker),
+            None => (),
+        }
+        for frame in scanner.stack().iter().rev() {
+            repaired.push(match frame {
+                Frame::Array(_) => ']',
+                Frame::Object(_) => '}',
+            });
+        }
+        repaired
+    };
+
+    *buf = Cursor::new(repaired);
+    Ok(())
+}
@@ -1,11 +1,30 @@
 use std::cmp::{max, min};
-use std::io::Cursor;
+use std::io::{Cursor, Write};
 
 use proptest::prelude::*;
+use regex::Regex;
+use structopt::StructOpt;
+use termcolor::NoColor;
 
-use super::{shorten, Opts};
+use crate::archive::Archive;
+use crate::filter::Filter;
+use crate::follow::{Follower, Watcher};
+use crate::format::Format;
+use crate::index::{self, Select};
+use crate::io::{Input, MAP_THRESHOLD};
+use crate::unicode::{self, Charset};
+
+use super::flatten::unflatten;
+use super::{
+    convert, extract, is_lines, lines, project, read_value, relax, repair, shorten, shorten_slice,
+    Encoding, Foreign, Opts, ParseError,
+};
 
 fn arb_json() -> impl Strategy<Value = json::Value> {
+    arb_json_with_keys("[[:alnum:]]*")
+}
+
+fn arb_json_with_keys(keys: &'static str) -> impl Strategy<Value = json::Value> {
     let leaf = prop_oneof![
         Just(json::Value::Null),
         any::<bool>().prop_map(json::Value::Bool),
@@ -14,10 +33,10 @@ fn arb_json() -> impl Strategy<Value = json::Value> {
             .prop_map(json::Value::Number),
         "[[:alnum:]]*".prop_map(json::Value::String),
     ];
-    leaf.prop_recursive(8, 512, 16, |inner| {
+    leaf.prop_recursive(8, 512, 16, move |inner| {
         prop_oneof![
             prop::collection::vec(inner.clone(), 0..10).prop_map(json::Value::Array),
-            prop::collection::hash_map("[[:alnum:]]*", inner, 0..10)
+            prop::collection::hash_map(keys, inner, 0..10)
                 .prop_map(|map| json::Value::Object(map.into_iter().collect())),
         ]
     })
@@ -30,7 +49,8 @@ prop_compose! {
     ) -> Opts {
         Opts {
             max_length,
-            max_depth
+            max_depth,
+            ..Opts::default()
         }
     }
 }
@@ -64,10 +84,7 @@ proptest! {
     #[test]
     fn identity(value in arb_json()) {
         let data = json::to_string_pretty(&value).unwrap();
-        prop_assert_eq!(run(Opts {
-            max_depth: None,
-            max_length: None,
-        }, &data), data)
+        prop_assert_eq!(run(Opts::default(), &data), data)
     }
 
     #[test]
@@ -97,4 +114,833 @@ proptest! {
             },
         }
     }
+
+    #[test]
+    fn flatten(value in arb_json_with_keys("[0-9a-z/~\\\\\"\t\n\r\x01]*"), js in any::<bool>()) {
+        let data = json::to_string_pretty(&value).unwrap();
+        let flattened = run(Opts {
+            flatten: true,
+            js,
+            ..Opts::default()
+        }, &data);
+        prop_assert_eq!(unflatten(&flattened).unwrap(), value)
+    }
+
+    #[test]
+    fn float_literals(literal in "-?(0|[1-9][0-9]{0,20})\\.[0-9]{1,20}([eE][+-]?[0-9]{1,3})?") {
+        check_literal(&literal)?;
+    }
+
+    #[test]
+    fn bigint_literals(literal in "-?[1-9][0-9]{18,60}") {
+        check_literal(&literal)?;
+    }
+}
+
+/// Checks that a number literal is written exactly as it appears in the input.
+fn check_literal(literal: &str) -> Result<(), TestCaseError> {
+    let data = format!("{{\"a\": [{}]}}", literal);
+    let pretty = format!("{{\n  \"a\": [\n    {}\n  ]\n}}", literal);
+    prop_assert_eq!(run(Opts::default(), &data), pretty.clone());
+    let opts = Opts {
+        max_length: Some(100),
+        ..Opts::default()
+    };
+    prop_assert_eq!(run(opts, &data), pretty);
+    let opts = Opts {
+        flatten: true,
+        ..Opts::default()
+    };
+    prop_assert_eq!(run(opts, &data), format!("/a = []\n/a/0 = {}\n", literal));
+
+    let mut result = Vec::new();
+    project(
+        Opts::default(),
+        "/a/0",
+        None,
+        Cursor::new(&data),
+        &mut result,
+    )
+    .unwrap();
+    prop_assert_eq!(String::from_utf8(result).unwrap(), literal);
+    let value = read_value(Cursor::new(&data)).unwrap();
+    prop_assert_eq!(
+        json::to_string(&value).unwrap(),
+        format!("{{\"a\":[{}]}}", literal)
+    );
+    Ok(())
+}
+
+#[test]
+fn filter() {
+    let data = r#"[{ "status": "ok" }, { "status": "failed" }, { "id": 2 }]"#;
+    let filter = "/status == failed".parse().unwrap();
+    let opts = Opts::default();
+
+    let mut result = Vec::new();
+    project(opts, "", Some(&filter), Cursor::new(data), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "[\n  /* 1 */ {\n    \"status\": \"failed\"\n  }\n]"
+    );
+
+    let data = r#"[{ "a": [1.0, { "b": 2e0 }] }, { "a": [1, { "b": 3 }] }]"#;
+    let filter = r#"/a == [1, {"b": 2}]"#.parse().unwrap();
+    let mut result = Vec::new();
+    project(opts, "", Some(&filter), Cursor::new(data), &mut result).unwrap();
+    assert!(String::from_utf8(result)
+        .unwrap()
+        .starts_with("[\n  /* 0 */ {"));
+
+    let matches = |filter: &str, value: &str| {
+        let filter: Filter = filter.parse().unwrap();
+        filter.matches(&json::from_str(value).unwrap())
+    };
+    assert!(matches(
+        "/id == 9007199254740993",
+        r#"{"id": 9007199254740993}"#
+    ));
+    assert!(!matches(
+        "/id == 9007199254740993",
+        r#"{"id": 9007199254740992}"#
+    ));
+    assert!(matches(
+        "/id > 9007199254740992",
+        r#"{"id": 9007199254740993}"#
+    ));
+    let big = "/id == 12345678901234567890123";
+    assert!(matches(big, r#"{"id": 12345678901234567890123}"#));
+    assert!(!matches(big, r#"{"id": 12345678901234567890124}"#));
+    assert!(matches("/id == 1", r#"{"id": 1.0}"#));
+}
+
+#[test]
+fn number_literals() {
+    // Number literals are only kept as written by the serde_json version pinned in
+    // Cargo.toml.
+    let data = "[1E+2, 1e-05, 1.50, -0.0, 12345678901234567890123]";
+    let expected = "[\n  1E+2,\n  1e-05,\n  1.50,\n  -0.0,\n  12345678901234567890123\n]";
+    let opts = Opts::default();
+    assert_eq!(
+        run(opts, data),
+        expected,
+        "streamed number literals changed, check the serde_json pin"
+    );
+    let mut result = Vec::new();
+    project(opts, "", None, Cursor::new(data), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        expected,
+        "number literals read into values changed, check the serde_json pin"
+    );
+}
+
+#[test]
+fn key_order() {
+    let data = r#"{ "z": 1, "a": { "y": 2, "b": 3 } }"#;
+    let opts = Opts::default();
+
+    let mut result = Vec::new();
+    project(opts, "/a", None, Cursor::new(data), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "{\n  \"y\": 2,\n  \"b\": 3\n}"
+    );
+
+    let opts = Opts {
+        sort_keys: true,
+        ..opts
+    };
+    let mut result = Vec::new();
+    project(opts, "/a", None, Cursor::new(data), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "{\n  \"b\": 3,\n  \"y\": 2\n}"
+    );
+    assert_eq!(
+        run(opts, data),
+        "{\n  \"a\": {\n    \"b\": 3,\n    \"y\": 2\n  },\n  \"z\": 1\n}"
+    );
+
+    let invalid = "{\"z\": 1,\n \"a\": x}";
+    let err = shorten_slice(opts, invalid.as_bytes(), Vec::new()).unwrap_err();
+    let err = err.downcast::<ParseError>().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "expected value at line 2 column 7 (in value at `/a`)"
+    );
+    assert_eq!(err.snippet(), " \"a\": x}");
+}
+
+#[test]
+fn pointer_not_found() {
+    let data = r#"{ "users": [{ "name": "a", "email": "b" }] }"#;
+    let opts = Opts::default();
+
+    let err = project(opts, "/users/emial", None, Cursor::new(data), Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No value found for JSON pointer `/users/emial`\n\
+         `emial` is not a valid index for the array at `/users`"
+    );
+    let err = project(opts, "/users/0/emial", None, Cursor::new(data), Vec::new()).unwrap_err();
+    assert!(err.to_string().ends_with("did you mean `/users/0/email`?"));
+    let err = project(opts, "users", None, Cursor::new(data), Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No value found for JSON pointer `users`\npointer must start with `/`"
+    );
+    for ptr in &["/users/00/name", "/users/+0/name"] {
+        let err = project(opts, ptr, None, Cursor::new(data), Vec::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("is not a valid index for the array at `/users`"));
+    }
+}
+
+#[test]
+fn parse_error() {
+    let data = "{\n  \"a\": [1, 2,, 3]\n}";
+    let shorten_err = |opts| {
+        shorten(opts, Cursor::new(data), Vec::new())
+            .unwrap_err()
+            .to_string()
+    };
+
+    let expected = "expected value at line 2 column 14 (in value at `/a/2`)";
+    assert_eq!(shorten_err(Opts::default()), expected);
+    assert_eq!(
+        shorten_err(Opts {
+            max_length: Some(1),
+            ..Opts::default()
+        }),
+        expected
+    );
+    let project_err = project(Opts::default(), "/a", None, Cursor::new(data), Vec::new());
+    assert_eq!(project_err.unwrap_err().to_string(), expected);
+}
+
+#[test]
+fn lenient() {
+    let mut buf = Cursor::new("{\n  \"a\": [1, {\"b\": 2}, {\"c\": \"xy".to_owned());
+    repair(&mut buf).unwrap();
+    assert_eq!(
+        buf.get_ref(),
+        "{\n  \"a\": [1, {\"b\": 2}, {\"<truncated here: unexpected EOF at line 2>\": null}]}"
+    );
+
+    let mut buf = Cursor::new("{\"a\": 1, \"b\": [1] 2}".to_owned());
+    repair(&mut buf).unwrap();
+    assert_eq!(
+        buf.get_ref(),
+        "{\"a\": 1, \"b\": [1], \"<truncated here: expected `,` or `}` at line 1>\": null}"
+    );
+}
+
+#[test]
+fn lenient_flag() {
+    let view = |args: &[&str]| {
+        let opts = crate::Opts::from_iter(args);
+        let input = Input::buffer("[1, 2, {\"x\": [3".to_owned(), "<test>");
+        let mut result = NoColor::new(Vec::new());
+        crate::view(&opts, input, None, &mut result).unwrap();
+        String::from_utf8(result.into_inner()).unwrap()
+    };
+
+    let expected = view(&["jv", "--lenient"]);
+    assert!(
+        expected.contains("\"<truncated here: unexpected EOF at line 1>\""),
+        "{}",
+        expected
+    );
+    assert_eq!(view(&["jv", "--jsonc", "--lenient"]), expected);
+}
+
+#[test]
+fn ndjson() {
+    let data = "{\"a\": [1, 2]}\n\n{\"b\": 2}\n{\"a\": 3}\n";
+    assert!(is_lines(data.as_bytes()));
+    assert!(!is_lines(b"{\n  \"a\": 1\n}\n"));
+
+    let mut result = NoColor::new(Vec::new());
+    lines(Opts::default(), "/a", None, Cursor::new(data), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result.into_inner()).unwrap(),
+        "// record 1\n[\n  1,\n  2\n]\n// record 3\n3\n"
+    );
+
+    let mut result = NoColor::new(Vec::new());
+    let err = lines(Opts::default(), "/c", None, Cursor::new(data), &mut result).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No value found for JSON pointer `/c` in any of the 3 records"
+    );
+}
+
+#[test]
+fn extracted() {
+    let data = "starting\nINFO req={\"a\": [1, 2]} took 3ms\nWARN [retry] body=[3, {]\n";
+    let mut result = NoColor::new(Vec::new());
+    extract(
+        Opts::default(),
+        None,
+        false,
+        "",
+        None,
+        Cursor::new(data),
+        &mut result,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(result.into_inner()).unwrap(),
+        "starting\nINFO req= … took 3ms\n{\n  \"a\": [\n    1,\n    2\n  ]\n}\nWARN [retry] body=[3, {]\n"
+    );
+
+    let anchor = Regex::new("body=").unwrap();
+    let data = "WARN [retry] body=[3, 4]\nINFO [5]\n";
+    let mut result = NoColor::new(Vec::new());
+    extract(
+        Opts::default(),
+        Some(&anchor),
+        true,
+        "/1",
+        None,
+        Cursor::new(data),
+        &mut result,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(result.into_inner()).unwrap(),
+        "WARN [retry] body=\n4\n"
+    );
+}
+
+#[test]
+fn follow() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.json");
+    std::fs::write(&path, "{\"old\": 1}\n").unwrap();
+    let mut follower = Follower::new(&path).unwrap();
+    let mut poll = || {
+        let mut lines = Vec::new();
+        follower
+            .poll(|line| {
+                lines.push(String::from_utf8(line.to_vec()).unwrap());
+                Ok(())
+            })
+            .unwrap();
+        lines
+    };
+    assert!(poll().is_empty());
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(b"{\"a\": 1}\n\n{\"b\":").unwrap();
+    assert_eq!(poll(), ["{\"a\": 1}"]);
+    file.write_all(b" 2}\n").unwrap();
+    assert_eq!(poll(), ["{\"b\": 2}"]);
+
+    std::fs::write(&path, "{}\n").unwrap();
+    assert_eq!(poll(), ["{}"]);
+
+    std::fs::rename(&path, dir.path().join("log.json.1")).unwrap();
+    std::fs::write(&path, "[1]\n[2]\n").unwrap();
+    assert_eq!(poll(), ["[1]", "[2]"]);
+}
+
+#[test]
+fn watch() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.json");
+    let mut watcher = Watcher::new(&path);
+    assert!(!watcher.changed());
+
+    std::fs::write(&path, "{}").unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+    std::fs::write(&path, "{\"a\": 1}").unwrap();
+    assert!(watcher.changed());
+}
+
+#[test]
+fn redraw() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.json");
+    let opts = crate::Opts::from_iter(&["jv"]);
+    let redraw = |last: &mut Vec<u8>| {
+        let mut result = NoColor::new(Vec::new());
+        crate::redraw(&opts, &path, last, &mut result).unwrap();
+        String::from_utf8(result.into_inner()).unwrap()
+    };
+
+    let mut last = Vec::new();
+    std::fs::write(&path, "{\"a\": [").unwrap();
+    assert_eq!(
+        redraw(&mut last),
+        format!(
+            "\x1b[2J\x1b[H// Failed to parse file `{}`: EOF while parsing a list at line 1 column 7 (in value at `/a/0`)\n",
+            path.display()
+        )
+    );
+
+    std::fs::write(&path, "{\"a\": [1, 2]}").unwrap();
+    let valid = "\x1b[2J\x1b[H{\n  \"a\": [\n    1,\n    2\n  ]\n}\n";
+    assert_eq!(redraw(&mut last), valid);
+
+    std::fs::write(&path, "{\"a\": [1,").unwrap();
+    let output = redraw(&mut last);
+    assert!(output.starts_with(valid), "{}", output);
+    assert_eq!(
+        &output[valid.len()..],
+        format!(
+            "// Failed to parse file `{}`: EOF while parsing a value at line 1 column 9 (in value at `/a/1`)\n",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn select_records() {
+    let data: String = (1..=3000)
+        .map(|n| format!("{{\"n\": {}}}\n\n", n))
+        .collect();
+    let select = |index: Option<&index::LineIndex>, select| {
+        let mut selected = Vec::new();
+        index::select_slice(data.as_bytes(), index, select, |number, line| {
+            selected.push((number, String::from_utf8(line.to_vec()).unwrap()));
+            Ok(())
+        })
+        .unwrap();
+        let mut streamed = Vec::new();
+        index::select_stream(data.as_bytes(), select, |number, line| {
+            streamed.push((number, String::from_utf8(line.to_vec()).unwrap()));
+            Ok(())
+        })
+        .unwrap();
+        (selected, streamed)
+    };
+
+    // Without an index, the tail of a file is numbered from the end rather than counting
+    // every record before it.
+    let (selected, streamed) = select(None, Select::Tail(2));
+    assert_eq!(
+        selected,
+        [
+            (-2, "{\"n\": 2999}".to_owned()),
+            (-1, "{\"n\": 3000}".to_owned())
+        ]
+    );
+    assert_eq!(
+        streamed,
+        [
+            (2999, "{\"n\": 2999}".to_owned()),
+            (3000, "{\"n\": 3000}".to_owned())
+        ]
+    );
+    let (selected, _) = select(None, Select::Record(2050));
+    assert_eq!(selected, [(2050, "{\"n\": 2050}".to_owned())]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.json");
+    std::fs::write(&path, &data).unwrap();
+    let meta = std::fs::metadata(&path).unwrap();
+    let built = index::open(dir.path(), &path, data.as_bytes(), &meta).unwrap();
+    assert_eq!(index::load(dir.path(), &path, &meta), Some(built));
+    let index = index::load(dir.path(), &path, &meta);
+    let (selected, _) = select(index.as_ref(), Select::Record(2050));
+    assert_eq!(selected, [(2050, "{\"n\": 2050}".to_owned())]);
+    let (selected, _) = select(index.as_ref(), Select::Tail(1));
+    assert_eq!(selected, [(3000, "{\"n\": 3000}".to_owned())]);
+    assert!(index::select_slice(
+        data.as_bytes(),
+        index.as_ref(),
+        Select::Record(3001),
+        |_, _| Ok(())
+    )
+    .is_err());
+
+    std::fs::write(&path, "{}\n").unwrap();
+    let meta = std::fs::metadata(&path).unwrap();
+    assert_eq!(index::load(dir.path(), &path, &meta), None);
+}
+
+#[test]
+fn tree_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.json");
+    let data = "{\"a\": {\"b/c\": [1, {\"x\": \"]\\\"}\"}]}, \"n\": []}";
+    std::fs::write(&path, data).unwrap();
+    index::build_tree(dir.path(), &path).unwrap();
+
+    let meta = std::fs::metadata(&path).unwrap();
+    let tree = index::load_tree(dir.path(), &path, &meta).unwrap();
+    let container = tree.get("/a/b~1c").unwrap();
+    assert!(container.array);
+    assert_eq!(&data[container.range.clone()], "[1, {\"x\": \"]\\\"}\"}]");
+    assert_eq!(tree.get("").unwrap().range, 0..data.len());
+    assert_eq!(
+        &data[tree.get("/a/b~1c/1").unwrap().range],
+        "{\"x\": \"]\\\"}\"}"
+    );
+    assert!(tree.get("/n").unwrap().array);
+    assert!(!tree.get("/a").unwrap().array);
+    assert!(tree.get("/a/b~1c/0").is_none());
+    assert!(tree.get("/b").is_none());
+    assert_eq!(
+        container.slice(data.as_bytes()),
+        Some(data[container.range.clone()].as_bytes())
+    );
+    assert_eq!(container.slice(data.replace('[', "(").as_bytes()), None);
+
+    let data = "{\"a\": {\"x\": [1]}, \"a\": {\"y\": {}}}";
+    std::fs::write(&path, data).unwrap();
+    index::build_tree(dir.path(), &path).unwrap();
+    let meta = std::fs::metadata(&path).unwrap();
+    let tree = index::load_tree(dir.path(), &path, &meta).unwrap();
+    assert_eq!(&data[tree.get("/a").unwrap().range], "{\"y\": {}}");
+    assert!(tree.get("/a/y").is_some());
+    assert!(tree.get("/a/x").is_none());
+
+    std::fs::write(&path, "{}").unwrap();
+    let meta = std::fs::metadata(&path).unwrap();
+    assert!(index::load_tree(dir.path(), &path, &meta).is_none());
+}
+
+#[test]
+fn concatenated() {
+    let data = "{\"a\": [1, 2]}[3, 4] 5";
+    let opts = Opts {
+        max_length: Some(3),
+        ..Opts::default()
+    };
+    assert_eq!(
+        run(opts, data),
+        "{\n  \"a\": [ 2 items... ]\n}\n[ 2 items... ]\n5"
+    );
+    assert_eq!(
+        run(
+            Opts {
+                slurp: true,
+                ..opts
+            },
+            data
+        ),
+        "[ 3 items... ]"
+    );
+
+    let mut result = Vec::new();
+    let filter = "> 3".parse().unwrap();
+    let opts = Opts {
+        slurp: true,
+        ..Opts::default()
+    };
+    project(opts, "", Some(&filter), Cursor::new(data), &mut result).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), "[\n  /* 2 */ 5\n]");
+}
+
+#[test]
+fn relaxed() {
+    let data = "{\n  // comment\n  \"a\": [1, /* 2 */ 3,],\n}";
+    let mut buf = Cursor::new(data.to_owned());
+    relax(&mut buf, false);
+    assert_eq!(
+        buf.get_ref(),
+        "{\n            \n  \"a\": [1,         3 ] \n}"
+    );
+
+    let data = "{ key: 'it\\'s \"x\"', hex: 0x1F, num: +.5, list: [Infinity,], }";
+    let mut buf = Cursor::new(data.to_owned());
+    relax(&mut buf, true);
+    let value: json::Value = json::from_str(buf.get_ref()).unwrap();
+    assert_eq!(
+        value,
+        json::json!({
+            "key": "it's \"x\"",
+            "hex": 31,
+            "num": 0.5,
+            "list": ["Infinity"],
+        })
+    );
+}
+
+#[test]
+fn yaml() {
+    let data = "a: [1, 2.5, .nan]\n1: ~\ntrue: {b: c}\n---\n- x\n";
+    let values = Foreign::Yaml.values(data.as_bytes()).unwrap();
+    assert_eq!(
+        json::to_string(&values).unwrap(),
+        "[{\"a\":[1,2.5,\"NaN\"],\"1\":null,\"true\":{\"b\":\"c\"}},[\"x\"]]"
+    );
+
+    let opts = Opts {
+        max_length: Some(6),
+        ..Opts::default()
+    };
+    let mut result = NoColor::new(Vec::new());
+    convert(
+        opts,
+        Foreign::Yaml,
+        data.as_bytes(),
+        "",
+        None,
+        false,
+        &mut result,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(result.into_inner()).unwrap(),
+        run(opts, &json::to_string(&values[0]).unwrap()) + "\n[\n  \"x\"\n]"
+    );
+}
+
+#[test]
+fn toml() {
+    let data = "a = 1\n[b]\nc = 1979-05-27T07:32:00Z\n[[d]]\ne = [1.5]\n";
+    let value = Foreign::Toml.value(data.as_bytes()).unwrap();
+    assert_eq!(
+        json::to_string(&value).unwrap(),
+        "{\"a\":1,\"b\":{\"c\":\"1979-05-27T07:32:00Z\"},\"d\":[{\"e\":[1.5]}]}"
+    );
+}
+
+#[test]
+fn binary() {
+    // {1: h'0102', "x": [1.5, -2]}
+    let cbor = b"\xa2\x01\x42\x01\x02\x61x\x82\xf9\x3e\x00\x21";
+    assert_eq!(
+        json::to_string(&Foreign::Cbor(Encoding::Hex).value(cbor).unwrap()).unwrap(),
+        "{\"1\":\"hex:0102 (2 bytes)\",\"x\":[1.5,-2]}"
+    );
+
+    // {"id": 1, "data": b"\x00\x01\xff"} 7
+    let msgpack = b"\x82\xa2id\x01\xa4data\xc4\x03\x00\x01\xff\x07";
+    assert_eq!(
+        json::to_string(&Foreign::MsgPack(Encoding::Base64).values(msgpack).unwrap()).unwrap(),
+        "[{\"id\":1,\"data\":\"base64:AAH/ (3 bytes)\"},7]"
+    );
+
+    // Small maps and arrays start with bytes used by both formats.
+    assert_eq!(Format::sniff(cbor), None);
+    assert_eq!(Format::sniff(msgpack), None);
+    assert_eq!(Format::sniff(b"\x82\x01\x02"), None);
+    assert_eq!(Format::sniff(b"\xd9\xd9\xf7\xa0"), Some(Format::Cbor));
+    assert_eq!(Format::sniff(b"\xde\x00\x00"), Some(Format::MsgPack));
+    assert_eq!(Format::sniff(b"{\"a\": 1}"), None);
+}
+
+#[test]
+fn compressed() {
+    let data = "{\"a\": [1, 2], \"b\": {\"c\": 3}}";
+    let opts = Opts {
+        max_length: Some(4),
+        ..Opts::default()
+    };
+    let expected = run(opts, data);
+
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(data.as_bytes()).unwrap();
+    let zstd = zstd::encode_all(data.as_bytes(), 0).unwrap();
+    let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+    xz.write_all(data.as_bytes()).unwrap();
+    for compressed in &[gzip.finish().unwrap(), zstd, xz.finish().unwrap()] {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(compressed).unwrap();
+
+        let mut result = Vec::new();
+        match Input::file(file.path()).unwrap() {
+            Input::File(rdr) => shorten(opts, rdr, Cursor::new(&mut result)).unwrap(),
+            _ => unreachable!(),
+        }
+        assert_eq!(String::from_utf8(result).unwrap(), expected);
+    }
+}
+
+#[test]
+fn read_values() {
+    assert_eq!(
+        read_value(Cursor::new("{\"a\": 1}")).unwrap(),
+        json::json!({"a": 1})
+    );
+    assert_eq!(
+        read_value(Cursor::new("{\"n\": 1}\n{\"n\": 2}\n")).unwrap(),
+        json::json!([{"n": 1}, {"n": 2}])
+    );
+}
+
+#[test]
+fn directories() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("b/c")).unwrap();
+    std::fs::write(dir.path().join("b/c/d.json"), "[1]").unwrap();
+    std::fs::write(dir.path().join("b/a.json"), "{\"x\": 2}").unwrap();
+    std::fs::write(dir.path().join("a.json"), "3").unwrap();
+    std::fs::write(dir.path().join("notes.txt"), "4").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.path(), dir.path().join("b/c/loop")).unwrap();
+
+    let opts = crate::Opts::from_iter(&["jv"]);
+    let value = crate::read_dir(&opts, dir.path()).unwrap();
+    assert_eq!(
+        json::to_string(&value).unwrap(),
+        "{\"a.json\":3,\"b\":{\"a.json\":{\"x\":2},\"c\":{\"d.json\":[1]}}}"
+    );
+}
+
+#[test]
+fn archives() {
+    let mut tar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    // Written directly, since the builder would remove the leading `./`.
+    header.as_gnu_mut().unwrap().name[..10].copy_from_slice(b"./a/b.json");
+    header.set_size(7);
+    header.set_cksum();
+    tar.append(&header, &b"[1, 2]\n"[..]).unwrap();
+    let tar = tar.into_inner().unwrap();
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("a/b.json", Default::default()).unwrap();
+    zip.write_all(b"[1, 2]\n").unwrap();
+    let zip = zip.finish().unwrap().into_inner();
+
+    for (data, kind) in &[(tar, Archive::Tar), (zip, Archive::Zip)] {
+        assert_eq!(Archive::detect(data), Some(*kind));
+        let mut entries = Vec::new();
+        kind.entries(Cursor::new(data), |name, rdr| {
+            let mut value = String::new();
+            rdr.read_to_string(&mut value)?;
+            entries.push((name.to_owned(), value));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(entries, [("a/b.json".to_owned(), "[1, 2]\n".to_owned())]);
+    }
+    assert_eq!(Archive::detect(b"[1, 2]"), None);
+}
+
+#[test]
+fn mmap() {
+    let data = "{\"a\": [1, 2], \"b\": {\"c\": 3}}\n[4, 5]";
+    let opts = Opts {
+        max_length: Some(4),
+        ..Opts::default()
+    };
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(data.as_bytes()).unwrap();
+    assert!(matches!(Input::file(file.path()).unwrap(), Input::File(_)));
+
+    // Only large files are mapped.
+    let padding = vec![b' '; MAP_THRESHOLD as usize];
+    file.write_all(&padding).unwrap();
+    assert!(matches!(
+        Input::open(file.path(), false).unwrap(),
+        Input::File(_)
+    ));
+    let mut result = Vec::new();
+    match Input::file(file.path()).unwrap() {
+        Input::Mmap(map) => shorten_slice(opts, map.data(), Cursor::new(&mut result)).unwrap(),
+        _ => panic!("file was not mapped"),
+    }
+    assert_eq!(String::from_utf8(result).unwrap(), run(opts, data));
+
+    let invalid = "{\"a\": [1, 2],\n \"b\": x}";
+    let err = shorten_slice(opts, invalid.as_bytes(), Vec::new()).unwrap_err();
+    let expected = shorten(opts, Cursor::new(invalid), Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), expected.to_string());
+    assert_eq!(
+        err.to_string(),
+        "expected value at line 2 column 7 (in value at `/b`)"
+    );
+
+    // Files which fail to map are read through a buffer instead.
+    let unmapped = Input::from_file_with(
+        std::fs::File::open(file.path()).unwrap(),
+        "file",
+        true,
+        |_| Err(std::io::Error::other("mapping is not supported")),
+    );
+    let mut result = Vec::new();
+    match unmapped.unwrap() {
+        Input::File(file) => shorten(opts, file, Cursor::new(&mut result)).unwrap(),
+        _ => panic!("file was mapped"),
+    }
+    assert_eq!(String::from_utf8(result).unwrap(), run(opts, data));
+
+    // Pipes cannot be mapped, so they are read through a buffer instead.
+    #[cfg(unix)]
+    {
+        use std::fs::File;
+        use std::os::fd::OwnedFd;
+
+        let (rdr, mut wtr) = std::io::pipe().unwrap();
+        wtr.write_all(data.as_bytes()).unwrap();
+        drop(wtr);
+
+        let mut result = Vec::new();
+        match Input::from_file(File::from(OwnedFd::from(rdr)), "pipe", true).unwrap() {
+            Input::File(file) => shorten(Opts::default(), file, Cursor::new(&mut result)).unwrap(),
+            _ => panic!("pipe was mapped"),
+        }
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            run(Opts::default(), data)
+        );
+    }
+}
+
+#[test]
+fn charsets() {
+    let utf16: Vec<u8> = "\u{feff}[\"\u{e9}\u{1f600}\"]"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    assert_eq!(Charset::detect(&utf16), Some(Charset::Utf16Le));
+    assert_eq!(
+        Charset::Utf16Le.decode(&utf16, false).unwrap(),
+        ("[\"\u{e9}\u{1f600}\"]".to_owned(), 0)
+    );
+    assert_eq!(Charset::detect(b"\0\0\0[\0\0\0]"), Some(Charset::Utf32Be));
+    assert_eq!(
+        Charset::Utf32Be.decode(b"\0\0\0[\0\0\0]", false).unwrap(),
+        ("[]".to_owned(), 0)
+    );
+    assert_eq!(Charset::detect(b"\xef\xbb\xbf[]"), Some(Charset::Utf8));
+    assert_eq!(Charset::detect(b"[]"), None);
+
+    let invalid = b"[\"a\xffb\"]\0";
+    assert!(unicode::utf8(invalid, false).is_err());
+    assert_eq!(
+        unicode::utf8(invalid, true).unwrap(),
+        ("[\"a\u{fffd}b\"]\0".to_owned(), 1)
+    );
+    assert!(Charset::Utf16Le.decode(b"[\0\0\xd8]\0", false).is_err());
+    assert_eq!(
+        Charset::Utf16Le.decode(b"[\0\0\xd8]\0\0", true).unwrap(),
+        ("[\u{fffd}]\u{fffd}".to_owned(), 2)
+    );
+}
+
+#[test]
+fn expand_strings() {
+    let data = r#"{"body": "{\"id\": 1, \"tags\": \"[1, 2]\"}", "text": "[not json"}"#;
+    let opts = Opts {
+        expand_strings: true,
+        ..Opts::default()
+    };
+    assert_eq!(
+        run(opts, data),
+        "{\n  \"body\": /* decoded */ {\n    \"id\": 1,\n    \"tags\": /* decoded */ [\n      1,\n      2\n    ]\n  },\n  \"text\": \"[not json\"\n}"
+    );
+    assert_eq!(
+        run(
+            Opts {
+                max_depth: Some(2),
+                ..opts
+            },
+            data
+        ),
+        "{\n  \"body\": /* decoded */ {\n    \"id\": 1,\n    \"tags\": /* decoded */ [ 2 items... ]\n  },\n  \"text\": \"[not json\"\n}"
+    );
+    assert!(!run(Opts::default(), data).contains("decoded"));
 }
@@ -0,0 +1,441 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use failure::{Error, Fail, Fallible};
+use termcolor::{ColorSpec, WriteColor};
+
+use super::pointer;
+
+// The number of bytes of the current line to keep for error snippets.
+const MAX_LINE: usize = 1024;
+// The number of bytes the parser may read past an error.
+const WINDOW: usize = 4096;
+// The number of characters to show either side of the error position in snippets.
+const SNIPPET_BEFORE: usize = 60;
+const SNIPPET_AFTER: usize = 40;
+
+/// An error in the syntax of the input.
+#[derive(Debug)]
+pub struct ParseError {
+    message: String,
+    line: usize,
+    column: usize,
+    pointer: String,
+    snippet: String,
+    caret: usize,
+}
+
+impl ParseError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+
+    /// The character offset of the error in the snippet.
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )?;
+        if !self.pointer.is_empty() {
+            write!(f, " (in value at `{}`)", self.pointer)?;
+        }
+        Ok(())
+    }
+}
+
+impl Fail for ParseError {}
+
+/// A reader which tracks the position of the parser in its input, to give context to
+/// parse errors.
+///
+/// The parser may read a little past the position of an error, so recently read bytes
+/// are kept in a window and only scanned once they are well behind the parser.
+pub struct Tracker<R> {
+    rdr: R,
+    error: Option<io::Error>,
+    window: VecDeque<u8>,
+    // The position of the scanner, which is at the start of the window.
+    line: usize,
+    column: usize,
+    // The end of the current line. If the line is very long, only the last `MAX_LINE`
+    // bytes are kept.
+    buf: Vec<u8>,
+    scanner: Scanner,
+}
+
+impl<R> Tracker<R>
+where
+    R: Read,
+{
+    pub fn new(rdr: R) -> Self {
+        Tracker {
+            rdr,
+            error: None,
+            window: VecDeque::new(),
+            line: 1,
+            column: 0,
+            buf: Vec::new(),
+            scanner: Scanner::default(),
+        }
+    }
+
+    /// Adds context to an error returned by a deserializer reading from this tracker.
+    pub fn error(&mut self, err: json::Error) -> Error {
+        match self.parse_error(err) {
+            Ok(err) => err.into(),
+            Err(err) => err,
+        }
+    }
+
+    /// Finds the position of a parse error, leaving the scanner at that position. Fails
+    /// with the original error if reading the input failed.
+    pub fn parse_error(&mut self, err: json::Error) -> Result<ParseError, Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err.into());
+        }
+
+        let (message, position) = split_position(&err);
+        // The error is at the last byte read by the parser, unless the input ended early.
+        let eof = message.starts_with("EOF");
+
+        let (line, column) = match position {
+            Some((line, column)) => {
+                let column = if eof {
+                    column
+                } else {
+                    column.saturating_sub(1)
+                };
+                while (self.line, self.column) < (line, column) {
+                    match self.window.pop_front() {
+                        Some(byte) => self.scan(byte),
+                        None => break,
+                    }
+                }
+                (line, column + if eof { 0 } else { 1 })
+            }
+            None => {
+                while let Some(byte) = self.window.pop_front() {
+                    self.scan(byte);
+                }
+                (self.line, self.column)
+            }
+        };
+
+        let mut before: Vec<char> = String::from_utf8_lossy(&self.buf)
+            .replace('\t', " ")
+            .chars()
+            .collect();
+        let mut after = self
+            .window
+            .iter()
+            .cloned()
+            .take_while(|&byte| byte != b'\n' && byte != b'\r')
+            .collect::<Vec<_>>();
+        if after.len() == self.window.len() {
+            self.read_rest_of_line(&mut after);
+        }
+        let after = String::from_utf8_lossy(&after).replace('\t', " ");
+
+        let mut snippet = String::new();
+        if before.len() > SNIPPET_BEFORE || self.column > self.buf.len() {
+            snippet.push_str("...");
+        }
+        let start = before.len().saturating_sub(SNIPPET_BEFORE);
+        snippet.extend(before.drain(start..));
+        let caret = snippet.chars().count();
+        snippet.extend(after.chars().take(SNIPPET_AFTER));
+
+        Ok(ParseError {
+            message,
+            line,
+            column,
+            pointer: self.scanner.pointer(),
+            snippet,
+            caret,
+        })
+    }
+
+    pub fn scanner(&self) -> &Scanner {
+        &self.scanner
+    }
+
+    fn read_rest_of_line(&mut self, rest: &mut Vec<u8>) {
+        let mut byte = 0;
+        while rest.len() < SNIPPET_AFTER * 4 {
+            match self.rdr.read(std::slice::from_mut(&mut byte)) {
+                Ok(1) if byte != b'\n' && byte != b'\r' => rest.push(byte),
+                _ => break,
+            }
+        }
+    }
+
+    fn scan(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 0;
+            self.buf.clear();
+        } else {
+            self.column += 1;
+            if self.buf.len() == MAX_LINE {
+                self.buf.drain(..MAX_LINE / 2);
+            }
+            self.buf.push(byte);
+        }
+        self.scanner.push(byte);
+    }
+}
+
+impl<R> Read for Tracker<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.rdr.read(buf) {
+            Ok(len) => {
+                for &byte in &buf[..len] {
+                    if self.window.len() == WINDOW {
+                        let byte = self.window.pop_front().unwrap();
+                        self.scan(byte);
+                    }
+                    self.window.push_back(byte);
+                }
+                Ok(len)
+            }
+            Err(err) => {
+                let copy = io::Error::new(err.kind(), err.to_string());
+                self.error = Some(err);
+                Err(copy)
+            }
+        }
+    }
+}
+
+/// Adds context to an error returned by a deserializer reading from a slice, by scanning
+/// the input up to the position of the error.
+pub fn slice_error(data: &[u8], err: json::Error) -> Error {
+    let end = split_position(&err).1.map_or(data.len(), |(line, column)| {
+        let start = data
+            .split(|&byte| byte == b'\n')
+            .take(line.saturating_sub(1))
+            .map(|line| line.len() + 1)
+            .sum::<usize>();
+        (start + column).min(data.len())
+    });
+
+    let mut rdr = Tracker::new(data);
+    match io::copy(&mut (&mut rdr).take(end as u64), &mut io::sink()) {
+        Ok(_) => rdr.error(err),
+        Err(err) => err.into(),
+    }
+}
+
+/// Separates the message of an error from its line and column, if it has them.
+fn split_position(err: &json::Error) -> (String, Option<(usize, usize)>) {
+    let mut message = err.to_string();
+    if err.line() == 0 {
+        return (message, None);
+    }
+
+    // Errors from nested values are converted to strings when transcoding, so the
+    // position may be repeated in the message.
+    let suffix = format!(" at line {} column {}", err.line(), err.column());
+    while message.ends_with(&suffix) {
+        message.truncate(message.len() - suffix.len());
+    }
+    (message, Some((err.line(), err.column())))
+}
+
+/// Calls `f` with a writer which keeps any write error, so it is not mistaken for an
+/// error in the input.
+pub fn writes<W, F>(wtr: W, f: F) -> Fallible<()>
+where
+    W: Write,
+    F: FnOnce(WriteTracker<W>) -> Fallible<()>,
+{
+    let mut error = None;
+    let result = f(WriteTracker {
+        wtr,
+        error: &mut error,
+    });
+    match error {
+        Some(err) => Err(err.into()),
+        None => result,
+    }
+}
+
+pub struct WriteTracker<'a, W> {
+    wtr: W,
+    error: &'a mut Option<io::Error>,
+}
+
+impl<W> Write for WriteTracker<'_, W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.wtr.write(buf).map_err(|err| self.keep(err))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush().map_err(|err| self.keep(err))
+    }
+}
+
+impl<W> WriteColor for WriteTracker<'_, W>
+where
+    W: WriteColor,
+{
+    fn supports_color(&self) -> bool {
+        self.wtr.supports_color()
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.wtr.set_color(spec).map_err(|err| self.keep(err))
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.wtr.reset().map_err(|err| self.keep(err))
+    }
+}
+
+impl<W> WriteTracker<'_, W> {
+    fn keep(&mut self, err: io::Error) -> io::Error {
+        let copy = io::Error::new(err.kind(), err.to_string());
+        if self.error.is_none() {
+            *self.error = Some(err);
+        }
+        copy
+    }
+}
+
+/// Follows the structure of JSON text to find the path to the value being parsed.
+#[derive(Debug, Default)]
+pub struct Scanner {
+    stack: Vec<Frame>,
+    string: bool,
+    escape: bool,
+    key: Option<Vec<u8>>,
+    scalar: bool,
+    offset: usize,
+    // The offset after the last complete value or opening bracket.
+    safe: usize,
+    // Whether the innermost container was empty at the safe offset.
+    empty: bool,
+}
+
+#[derive(Debug)]
+pub enum Frame {
+    Array(usize),
+    Object(Option<String>),
+}
+
+impl Scanner {
+    /// The offset of the end of the last complete value or opening bracket. The input up to
+    /// this point can be made valid by closing the containers in `stack`.
+    pub fn safe(&self) -> usize {
+        self.safe
+    }
+
+    /// Whether the innermost container has no complete values before the safe offset.
+    pub fn empty(&self) -> bool {
+        self.empty
+    }
+
+    pub fn stack(&self) -> &[Frame] {
+        &self.stack
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.offset += 1;
+        if self.string {
+            if self.escape {
+                self.escape = false;
+            } else if byte == b'\\' {
+                self.escape = true;
+            } else if byte == b'"' {
+                self.string = false;
+                match self.key.take() {
+                    Some(key) => {
+                        if let Some(Frame::Object(last)) = self.stack.last_mut() {
+                            *last = Some(String::from_utf8_lossy(&key).into_owned());
+                        }
+                    }
+                    None => self.complete(self.offset),
+                }
+                return;
+            }
+
+            if let Some(key) = &mut self.key {
+                key.push(byte);
+            }
+            return;
+        }
+
+        if self.scalar && !(byte.is_ascii_alphanumeric() || b"+-.".contains(&byte)) {
+            self.scalar = false;
+            self.complete(self.offset - 1);
+        }
+
+        match byte {
+            b'"' => {
+                self.string = true;
+                if let Some(Frame::Object(None)) = self.stack.last() {
+                    self.key = Some(Vec::new());
+                }
+            }
+            b'{' | b'[' => {
+                self.stack.push(if byte == b'{' {
+                    Frame::Object(None)
+                } else {
+                    Frame::Array(0)
+                });
+                self.safe = self.offset;
+                self.empty = true;
+            }
+            b'}' | b']' => {
+                self.stack.pop();
+                self.complete(self.offset);
+            }
+            b',' => match self.stack.last_mut() {
+                Some(Frame::Array(index)) => *index += 1,
+                Some(Frame::Object(key)) => *key = None,
+                None => (),
+            },
+            b' ' | b'\t' | b'\n' | b'\r' | b':' => (),
+            _ => self.scalar = true,
+        }
+    }
+
+    fn complete(&mut self, offset: usize) {
+        self.safe = offset;
+        self.empty = false;
+    }
+
+    fn pointer(&self) -> String {
+        let mut ptr = String::new();
+        for frame in &self.stack {
+            match frame {
+                Frame::Array(index) => ptr.push_str(&format!("/{}", index)),
+                Frame::Object(Some(key)) => ptr.push_str(&format!("/{}", pointer::escape(key))),
+                Frame::Object(None) => break,
+            }
+        }
+        ptr
+    }
+}
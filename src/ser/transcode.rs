@@ -0,0 +1,371 @@
+//! Transcoding values from a deserializer to a JSON serializer.
+//!
+//! This follows `serde_transcode`, which cannot be used directly because some values must
+//! be changed on the way through rather than forwarded: map keys which are not strings,
+//! byte strings, TOML datetimes, non-finite floats and the number literals of `json`
+//! itself. Only the visitor and seeds needed to intercept those values are kept here.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+use failure::{bail, Error};
+
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, Serializer};
+
+/// The encoding used to write byte strings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl Encoding {
+    pub const NAMES: &'static [&'static str] = &["base64", "hex"];
+
+    fn encode(self, v: &[u8]) -> String {
+        match self {
+            Encoding::Base64 => format!("base64:{} ({} bytes)", base64::encode(v), v.len()),
+            Encoding::Hex => {
+                let hex: String = v.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("hex:{} ({} bytes)", hex, v.len())
+            }
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "base64" => Ok(Encoding::Base64),
+            "hex" => Ok(Encoding::Hex),
+            _ => bail!("Unknown encoding `{}`", s),
+        }
+    }
+}
+
+// The field name the `toml` crate uses to pass datetimes through serde.
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+// The name `json` uses to pass number literals through serde, with its
+// `arbitrary_precision` feature. It is private, so `json` is pinned to one version.
+const JSON_NUMBER: &str = "$serde_json::private::Number";
+
+/// Serializes the value read from a deserializer, converting values with no representation
+/// in JSON: map keys which are not strings are written as strings, TOML datetimes are
+/// written as strings, byte strings are written as strings in the given encoding, and
+/// non-finite floats are written as the strings `"NaN"`, `"Infinity"` and `"-Infinity"`.
+/// JSON numbers are written exactly as they appear in the input.
+pub struct Transcoder<D>(RefCell<Option<D>>, Encoding);
+
+impl<'de, D> Transcoder<D>
+where
+    D: Deserializer<'de>,
+{
+    pub fn new(d: D, bytes: Encoding) -> Self {
+        Transcoder(RefCell::new(Some(d)), bytes)
+    }
+}
+
+impl<'de, D> Serialize for Transcoder<D>
+where
+    D: Deserializer<'de>,
+{
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0
+            .borrow_mut()
+            .take()
+            .unwrap()
+            .deserialize_any(Visitor(s, self.1))
+            .map_err(d2s)
+    }
+}
+
+struct Visitor<S>(S, Encoding);
+
+impl<'de, S> de::Visitor<'de> for Visitor<S>
+where
+    S: Serializer,
+{
+    type Value = S::Ok;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "any value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_bool(v).map_err(s2d)
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_i64(v).map_err(s2d)
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_i128(v).map_err(s2d)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_u64(v).map_err(s2d)
+    }
+
+    fn visit_u128<E>(self, v: u128) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_u128(v).map_err(s2d)
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        if v.is_finite() {
+            self.0.serialize_f64(v).map_err(s2d)
+        } else {
+            self.0.serialize_str(&non_finite(v)).map_err(s2d)
+        }
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_str(v).map_err(s2d)
+    }
+
+    fn visit_unit<E>(self) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_unit().map_err(s2d)
+    }
+
+    fn visit_none<E>(self) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_none().map_err(s2d)
+    }
+
+    fn visit_some<D>(self, d: D) -> Result<S::Ok, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0
+            .serialize_some(&Transcoder::new(d, self.1))
+            .map_err(s2d)
+    }
+
+    fn visit_newtype_struct<D>(self, d: D) -> Result<S::Ok, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Transcoder::new(d, self.1).serialize(self.0).map_err(s2d)
+    }
+
+    fn visit_seq<V>(self, mut v: V) -> Result<S::Ok, V::Error>
+    where
+        V: de::SeqAccess<'de>,
+    {
+        let mut s = self.0.serialize_seq(v.size_hint()).map_err(s2d)?;
+        while let Some(()) = v.next_element_seed(SeqSeed(&mut s, self.1))? {}
+        s.end().map_err(s2d)
+    }
+
+    fn visit_map<V>(self, mut v: V) -> Result<S::Ok, V::Error>
+    where
+        V: de::MapAccess<'de>,
+    {
+        let first = v.next_key_seed(KeySeed(self.1))?;
+        if first.as_deref() == Some(TOML_DATETIME) {
+            let datetime: String = v.next_value()?;
+            return self.0.serialize_str(&datetime).map_err(s2d);
+        } else if first.as_deref() == Some(JSON_NUMBER) {
+            let number: String = v.next_value()?;
+            let mut s = self.0.serialize_struct(JSON_NUMBER, 1).map_err(s2d)?;
+            s.serialize_field(JSON_NUMBER, &number).map_err(s2d)?;
+            return s.end().map_err(s2d);
+        }
+
+        let mut s = self.0.serialize_map(v.size_hint()).map_err(s2d)?;
+        let mut key = first;
+        while let Some(k) = key {
+            s.serialize_key(&k).map_err(s2d)?;
+            v.next_value_seed(ValueSeed(&mut s, self.1))?;
+            key = v.next_key_seed(KeySeed(self.1))?;
+        }
+        s.end().map_err(s2d)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<S::Ok, E>
+    where
+        E: de::Error,
+    {
+        self.0.serialize_str(&self.1.encode(v)).map_err(s2d)
+    }
+}
+
+struct SeqSeed<'a, S>(&'a mut S, Encoding);
+
+impl<'de, S> de::DeserializeSeed<'de> for SeqSeed<'_, S>
+where
+    S: SerializeSeq,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0
+            .serialize_element(&Transcoder::new(deserializer, self.1))
+            .map_err(s2d)
+    }
+}
+
+/// Reads a map key as a string.
+struct KeySeed(Encoding);
+
+impl<'de> de::DeserializeSeed<'de> for KeySeed {
+    type Value = String;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> de::Visitor<'de> for KeySeed {
+    type Value = String;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "a string, number, boolean or null map key")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u128<E>(self, v: u128) -> Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<String, E> {
+        if v.is_finite() {
+            Ok(v.to_string())
+        } else {
+            Ok(non_finite(v))
+        }
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<String, E> {
+        Ok(v.to_owned())
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<String, E> {
+        Ok(v)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<String, E> {
+        Ok(self.0.encode(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<String, E> {
+        Ok("null".to_owned())
+    }
+
+    fn visit_none<E>(self) -> Result<String, E> {
+        Ok("null".to_owned())
+    }
+
+    fn visit_some<D>(self, d: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        d.deserialize_any(self)
+    }
+
+    fn visit_newtype_struct<D>(self, d: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        d.deserialize_any(self)
+    }
+}
+
+struct ValueSeed<'a, S>(&'a mut S, Encoding);
+
+impl<'de, S> de::DeserializeSeed<'de> for ValueSeed<'_, S>
+where
+    S: SerializeMap,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.0
+            .serialize_value(&Transcoder::new(deserializer, self.1))
+            .map_err(s2d)
+    }
+}
+
+fn non_finite(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_owned()
+    } else if v > 0.0 {
+        "Infinity".to_owned()
+    } else {
+        "-Infinity".to_owned()
+    }
+}
+
+fn d2s<D, S>(d: D) -> S
+where
+    D: de::Error,
+    S: ser::Error,
+{
+    S::custom(d.to_string())
+}
+
+fn s2d<S, D>(s: S) -> D
+where
+    S: ser::Error,
+    D: de::Error,
+{
+    D::custom(s.to_string())
+}
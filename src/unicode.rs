@@ -0,0 +1,118 @@
+use std::fmt;
+
+use failure::{bail, Fallible};
+
+/// The encoding of text input, when it is not plain UTF-8.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Charset {
+    /// UTF-8 with a byte order mark.
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+}
+
+impl Charset {
+    /// Detects the encoding from a byte order mark or, since JSON text starts with an ASCII
+    /// character, from the pattern of zero bytes at the start of the input.
+    pub fn detect(buf: &[u8]) -> Option<Self> {
+        match buf {
+            [0xef, 0xbb, 0xbf, ..] => Some(Charset::Utf8),
+            [0xff, 0xfe, 0, 0, ..] | [0x01..=0x7f, 0, 0, 0, ..] => Some(Charset::Utf32Le),
+            [0, 0, 0xfe, 0xff, ..] | [0, 0, 0, 0x01..=0x7f, ..] => Some(Charset::Utf32Be),
+            [0xff, 0xfe, ..] | [0x01..=0x7f, 0, ..] => Some(Charset::Utf16Le),
+            [0xfe, 0xff, ..] | [0, 0x01..=0x7f, ..] => Some(Charset::Utf16Be),
+            _ => None,
+        }
+    }
+
+    /// Converts text in this encoding to UTF-8, removing any byte order mark. If `lossy`
+    /// is set, invalid sequences are replaced with U+FFFD, and the number replaced is
+    /// returned.
+    pub fn decode(self, data: &[u8], lossy: bool) -> Fallible<(String, usize)> {
+        let mut text = String::with_capacity(data.len());
+        let mut invalid = 0;
+        let mut offset = 0;
+        let mut push = |c: Option<char>, len: usize| {
+            match c {
+                Some(c) => text.push(c),
+                None if lossy => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    invalid += 1;
+                }
+                None => bail!("Invalid {} at byte offset {}", self, offset),
+            }
+            offset += len;
+            Ok(())
+        };
+
+        let width = match self {
+            Charset::Utf8 => {
+                return utf8(data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data), lossy)
+            }
+            Charset::Utf16Le | Charset::Utf16Be => {
+                let units = data.chunks_exact(2).map(|unit| match self {
+                    Charset::Utf16Le => u16::from_le_bytes([unit[0], unit[1]]),
+                    _ => u16::from_be_bytes([unit[0], unit[1]]),
+                });
+                for c in char::decode_utf16(units) {
+                    let c = c.ok();
+                    push(c, c.map_or(1, char::len_utf16) * 2)?;
+                }
+                2
+            }
+            Charset::Utf32Le | Charset::Utf32Be => {
+                for unit in data.chunks_exact(4) {
+                    let unit = [unit[0], unit[1], unit[2], unit[3]];
+                    let unit = match self {
+                        Charset::Utf32Le => u32::from_le_bytes(unit),
+                        _ => u32::from_be_bytes(unit),
+                    };
+                    push(char::from_u32(unit), 4)?;
+                }
+                4
+            }
+        };
+        // A trailing partial code unit.
+        let rest = data.len() % width;
+        if rest > 0 {
+            push(None, rest)?;
+        }
+
+        if text.starts_with('\u{feff}') {
+            text.remove(0);
+        }
+        Ok((text, invalid))
+    }
+}
+
+/// Checks that data is valid UTF-8. If `lossy` is set, invalid sequences are replaced with
+/// U+FFFD, and the number replaced is returned.
+pub fn utf8(data: &[u8], lossy: bool) -> Fallible<(String, usize)> {
+    let mut text = String::with_capacity(data.len());
+    let mut invalid = 0;
+    for chunk in data.utf8_chunks() {
+        text.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            if !lossy {
+                bail!("Invalid UTF-8 at byte offset {}", text.len());
+            }
+            text.push(char::REPLACEMENT_CHARACTER);
+            invalid += 1;
+        }
+    }
+    Ok((text, invalid))
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf16Le => "UTF-16LE",
+            Charset::Utf16Be => "UTF-16BE",
+            Charset::Utf32Le => "UTF-32LE",
+            Charset::Utf32Be => "UTF-32BE",
+        })
+    }
+}